@@ -0,0 +1,192 @@
+//! Overhead of the hook on files other than the `auth-user-pass` file, which
+//! is what every other file read by OpenVPN pays (its config, certificates,
+//! CRLs, etc.).
+//!
+//! The hook can't be linked into the benchmark (it is a `cdylib`), so it is
+//! loaded with `dlopen`, and its replacements are called directly, next to the
+//! original libc functions. Like the tests, this requires building the hook
+//! first:
+//!
+//! ```shell
+//! cargo build --release && cargo bench
+//! ```
+
+use std::{
+    ffi::{CStr, CString},
+    hint::black_box,
+    io::Write,
+    mem,
+};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use libc::{c_char, c_int, c_void, size_t, FILE, RTLD_LOCAL, RTLD_NOW};
+use tempfile::NamedTempFile;
+
+/// The hook, as built with `cargo build --release`.
+const HOOK_PATH: &CStr = c"target/release/libopenvpn_auth_hook.so";
+
+/// Size of the `fgets` and `fread` buffers (same as OpenVPN's for most files).
+const BUFFER_SIZE: usize = 256;
+
+type Fopen = unsafe extern "C" fn(*const c_char, *const c_char) -> *mut FILE;
+type Fgets = unsafe extern "C" fn(*mut c_char, c_int, *mut FILE) -> *mut c_char;
+type Fgetc = unsafe extern "C" fn(*mut FILE) -> c_int;
+type Fread = unsafe extern "C" fn(*mut c_void, size_t, size_t, *mut FILE) -> size_t;
+type Fclose = unsafe extern "C" fn(*mut FILE) -> c_int;
+
+/// The functions being compared, either the original libc ones or the hook
+/// replacements.
+struct Functions {
+    fopen: Fopen,
+    fgets: Fgets,
+    fgetc: Fgetc,
+    fread: Fread,
+    fclose: Fclose,
+}
+
+impl Functions {
+    fn unhooked() -> Self {
+        Functions {
+            fopen: libc::fopen,
+            fgets: libc::fgets,
+            fgetc: libc::fgetc,
+            fread: libc::fread,
+            fclose: libc::fclose,
+        }
+    }
+
+    fn hooked() -> Self {
+        // The auth file is never opened by the benchmark
+        std::env::set_var("AUTH_FILE_PATH", "/nonexistent/auth-user-pass.txt");
+        let handle = unsafe { libc::dlopen(HOOK_PATH.as_ptr(), RTLD_NOW | RTLD_LOCAL) };
+        assert!(
+            !handle.is_null(),
+            "Unable to load {HOOK_PATH:?}. Build it first with `cargo build --release`"
+        );
+        unsafe {
+            Functions {
+                fopen: mem::transmute::<*mut libc::c_void, Fopen>(symbol(handle, c"fopen")),
+                fgets: mem::transmute::<*mut libc::c_void, Fgets>(symbol(handle, c"fgets")),
+                fgetc: mem::transmute::<*mut libc::c_void, Fgetc>(symbol(handle, c"fgetc")),
+                fread: mem::transmute::<*mut libc::c_void, Fread>(symbol(handle, c"fread")),
+                fclose: mem::transmute::<*mut libc::c_void, Fclose>(symbol(handle, c"fclose")),
+            }
+        }
+    }
+
+    /// Open `path` and close it right away.
+    fn open_and_close(&self, path: &CStr) {
+        unsafe {
+            let stream = (self.fopen)(path.as_ptr(), c"r".as_ptr());
+            assert!(!stream.is_null());
+            (self.fclose)(black_box(stream));
+        }
+    }
+
+    /// Open `path` and read it whole with `fgets`. Returns the number of
+    /// lines read.
+    fn read_lines(&self, path: &CStr) -> usize {
+        let mut buffer = [0 as c_char; BUFFER_SIZE];
+        let mut lines = 0;
+        unsafe {
+            let stream = (self.fopen)(path.as_ptr(), c"r".as_ptr());
+            assert!(!stream.is_null());
+            while !(self.fgets)(buffer.as_mut_ptr(), BUFFER_SIZE as c_int, stream).is_null() {
+                lines += 1;
+            }
+            (self.fclose)(stream);
+        }
+        lines
+    }
+
+    /// Open `path` and read it whole with `fgetc`. Returns the number of
+    /// bytes read.
+    fn read_bytes(&self, path: &CStr) -> usize {
+        let mut bytes = 0;
+        unsafe {
+            let stream = (self.fopen)(path.as_ptr(), c"r".as_ptr());
+            assert!(!stream.is_null());
+            while (self.fgetc)(stream) != libc::EOF {
+                bytes += 1;
+            }
+            (self.fclose)(stream);
+        }
+        bytes
+    }
+
+    /// Open `path` and read it whole with `fread`, `BUFFER_SIZE` bytes at a
+    /// time. Returns the number of bytes read.
+    fn read_blocks(&self, path: &CStr) -> usize {
+        let mut buffer = [0u8; BUFFER_SIZE];
+        let mut bytes = 0;
+        unsafe {
+            let stream = (self.fopen)(path.as_ptr(), c"r".as_ptr());
+            assert!(!stream.is_null());
+            loop {
+                let read = (self.fread)(buffer.as_mut_ptr().cast(), 1, BUFFER_SIZE, stream);
+                if read == 0 {
+                    break;
+                }
+                bytes += read;
+            }
+            (self.fclose)(stream);
+        }
+        bytes
+    }
+}
+
+/// Address of `name` in the library loaded as `handle`.
+unsafe fn symbol(handle: *mut libc::c_void, name: &CStr) -> *mut libc::c_void {
+    let address = libc::dlsym(handle, name.as_ptr());
+    assert!(!address.is_null(), "Symbol {name:?} not found");
+    address
+}
+
+/// A file like a PEM certificate or CRL: many short lines.
+fn create_file() -> NamedTempFile {
+    let mut file = NamedTempFile::new().unwrap();
+    for _ in 0..1000 {
+        writeln!(file, "{}", "A".repeat(64)).unwrap();
+    }
+    file
+}
+
+fn bench_hooks(c: &mut Criterion) {
+    let file = create_file();
+    let path = CString::new(file.path().to_str().unwrap()).unwrap();
+    let size = file.as_file().metadata().unwrap().len();
+    let variants = [
+        ("unhooked", Functions::unhooked()),
+        ("hooked", Functions::hooked()),
+    ];
+
+    let mut group = c.benchmark_group("fopen");
+    for (name, functions) in &variants {
+        group.bench_function(*name, |b| b.iter(|| functions.open_and_close(&path)));
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("fgets");
+    group.throughput(Throughput::Bytes(size));
+    for (name, functions) in &variants {
+        group.bench_function(*name, |b| b.iter(|| functions.read_lines(&path)));
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("fgetc");
+    group.throughput(Throughput::Bytes(size));
+    for (name, functions) in &variants {
+        group.bench_function(*name, |b| b.iter(|| functions.read_bytes(&path)));
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("fread");
+    group.throughput(Throughput::Bytes(size));
+    for (name, functions) in &variants {
+        group.bench_function(*name, |b| b.iter(|| functions.read_blocks(&path)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_hooks);
+criterion_main!(benches);
@@ -1,5 +1,20 @@
 //! Generates the encrypted password and nonce at compile time. Both will be
-//! stored in the resulting binary. The application identifier
+//! stored in the resulting binary. The password given in `BUILD_ARG_PASSWORD`
+//! is the `default` secret. Additional named secrets can be given by listing
+//! their names in `BUILD_ARG_SECRET_NAMES` (comma separated), and each secret
+//! in `BUILD_ARG_SECRET_<NAME>` (upper case, with `-` replaced by `_`). They
+//! are all written to `secrets.rs` in `OUT_DIR`, which is included by
+//! `params.rs`.
+//!
+//! Optionally, each secret can also have a username, which then replaces the
+//! username line of the auth files too: `BUILD_ARG_USERNAME` for the `default`
+//! secret, and `BUILD_ARG_SECRET_<NAME>_USERNAME` for the named ones.
+//!
+//! The credentials of the proxy that OpenVPN connects through (if any) are
+//! given separately, in `BUILD_ARG_PROXY_PASSWORD` and (optionally)
+//! `BUILD_ARG_PROXY_USERNAME`, and become the `proxy` secret.
+//!
+//! The application identifier
 //! (`BUILD_ARG_APP_ID`) and the machine identifier (system's machine-id) are
 //! used to generate the symmetric encryption key.
 //!
@@ -7,18 +22,111 @@
 //! application identifier and the machine identifier. Therefore, the encrypted
 //! password can only be decrypted on the same machine where it was generated
 //! (where the crate was compiled).
+//!
+//! It also compiles the C part of the hook (`src/variadic.c`).
+
+use std::{env, fmt::Write, fs, path::Path};
 
 use encryption::encrypt;
 
+/// Name of the secret given in `BUILD_ARG_PASSWORD`.
+const DEFAULT_SECRET: &str = "default";
+
+/// Name of the secret given in `BUILD_ARG_PROXY_PASSWORD`.
+const PROXY_SECRET: &str = "proxy";
+
 fn main() {
-    let plaintext = env!("BUILD_ARG_PASSWORD").as_bytes();
-    let (nonce, ciphertext) = encrypt(plaintext).expect("compile-time password encryption failed");
-    let nonce_hex = hex::encode(nonce);
-    let ciphertext_hex = hex::encode(ciphertext);
+    cc::Build::new().file("src/variadic.c").compile("variadic");
+    println!("cargo:rerun-if-changed=src/variadic.c");
+
     println!("cargo:rerun-if-env-changed=BUILD_ARG_PASSWORD");
-    println!("cargo:rustc-env=BUILD_ARG_NONCE_HEX={}", nonce_hex);
-    println!(
-        "cargo:rustc-env=BUILD_ARG_CIPHERTEXT_HEX={}",
-        ciphertext_hex
-    );
+    let mut secrets = vec![Secret {
+        name: DEFAULT_SECRET.to_owned(),
+        password: env!("BUILD_ARG_PASSWORD").to_owned(),
+        username: optional_var("BUILD_ARG_USERNAME"),
+    }];
+    if let Some(password) = optional_var("BUILD_ARG_PROXY_PASSWORD") {
+        secrets.push(Secret {
+            name: PROXY_SECRET.to_owned(),
+            password,
+            username: optional_var("BUILD_ARG_PROXY_USERNAME"),
+        });
+    }
+    secrets.extend(named_secrets());
+
+    let mut generated = String::from("const SECRETS: &[EncryptedSecret] = &[\n");
+    for secret in secrets {
+        let username = match secret.username {
+            Some(username) => format!("Some({})", encrypted_value(&username)),
+            None => "None".to_owned(),
+        };
+        writeln!(
+            generated,
+            "    EncryptedSecret {{ name: {:?}, password: {}, username: {username} }},",
+            secret.name,
+            encrypted_value(&secret.password),
+        )
+        .unwrap();
+    }
+    generated.push_str("];\n");
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    fs::write(Path::new(&out_dir).join("secrets.rs"), generated)
+        .expect("unable to write the encrypted secrets");
+}
+
+/// A secret to embed, in plain text.
+struct Secret {
+    name: String,
+    password: String,
+    username: Option<String>,
+}
+
+/// The additional secrets listed in `BUILD_ARG_SECRET_NAMES`.
+fn named_secrets() -> Vec<Secret> {
+    println!("cargo:rerun-if-env-changed=BUILD_ARG_SECRET_NAMES");
+    let Some(names) = optional_var("BUILD_ARG_SECRET_NAMES") else {
+        return Vec::new();
+    };
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            assert!(
+                name != DEFAULT_SECRET,
+                "the '{DEFAULT_SECRET}' secret is given in BUILD_ARG_PASSWORD"
+            );
+            assert!(
+                name != PROXY_SECRET,
+                "the '{PROXY_SECRET}' secret is given in BUILD_ARG_PROXY_PASSWORD"
+            );
+            let variable = format!("BUILD_ARG_SECRET_{}", name.to_uppercase().replace('-', "_"));
+            let password = optional_var(&variable)
+                .unwrap_or_else(|| panic!("{variable} must be set for the '{name}' secret"));
+            Secret {
+                name: name.to_owned(),
+                password,
+                username: optional_var(&format!("{variable}_USERNAME")),
+            }
+        })
+        .collect()
+}
+
+/// The value of the environment variable `name`, if set and not empty (so
+/// that the test values in `.cargo/config.toml` can be blanked out, see
+/// `build.sh`). The build is run again whenever it changes.
+fn optional_var(name: &str) -> Option<String> {
+    println!("cargo:rerun-if-env-changed={name}");
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// `plaintext` encrypted, as an `EncryptedValue` expression.
+fn encrypted_value(plaintext: &str) -> String {
+    let (nonce, ciphertext) =
+        encrypt(plaintext.as_bytes()).expect("compile-time password encryption failed");
+    format!(
+        "EncryptedValue {{ nonce_hex: {:?}, ciphertext_hex: {:?} }}",
+        hex::encode(nonce),
+        hex::encode(ciphertext),
+    )
 }
@@ -4,5 +4,18 @@ set -e
 
 read -sp "Password: " BUILD_ARG_PASSWORD
 echo
+read -p "Username (leave empty to keep the one in the auth file): " BUILD_ARG_USERNAME
+read -sp "Proxy password (leave empty if there's no proxy authentication): " BUILD_ARG_PROXY_PASSWORD
+echo
+if [ -n "$BUILD_ARG_PROXY_PASSWORD" ]; then
+  read -p "Proxy username (leave empty to keep the one in the proxy authfile): " BUILD_ARG_PROXY_USERNAME
+fi
+export BUILD_ARG_USERNAME
+# Cargo fills in the test secrets and proxy credentials of `.cargo/config.toml`
+# for the variables that are not set, so those not given (or left empty above)
+# are set to empty explicitly (i.e., no secret)
+for var in $(sed -n 's/^\(BUILD_ARG_\(SECRET\|PROXY\)_[A-Z0-9_]*\) *=.*/\1/p' .cargo/config.toml); do
+  export "$var=${!var-}"
+done
 BUILD_ARG_PASSWORD="$BUILD_ARG_PASSWORD" cargo build --release
 echo 'The dynamic library should be available at target/release/libopenvpn_auth_hook.so'
@@ -4,10 +4,14 @@
 //! Internally manages the generation of the encryption key and the nonce value.
 //! The encryption key is generated from the application identifier (a constant
 //! hardcoded value) and the machine identifier (read from the filesystem).
+//!
+//! Whole files can be encrypted too (see `seal`), in chunks that are each
+//! authenticated on their own. They are still decrypted whole (see
+//! `open_sealed`), so the whole plaintext is held in memory.
 use std::io;
 
 use aes_gcm::{
-    aead::{Aead, OsRng},
+    aead::{Aead, OsRng, Payload},
     AeadCore, Aes256Gcm, KeyInit,
 };
 use hkdf::Hkdf;
@@ -21,6 +25,9 @@ pub enum Error {
 
     #[error("cipher error (opaque)")]
     Cipher,
+
+    #[error("not a sealed file")]
+    NotSealed,
 }
 
 /// Size of the encryption key generated with
@@ -61,6 +68,85 @@ pub fn decrypt(nonce: &[u8; NONCE_SIZE], ciphertext: &[u8]) -> Result<Vec<u8>, E
         .map_err(|_| Error::Cipher)
 }
 
+/// Magic bytes at the beginning of sealed files, which also identify the
+/// version of the format.
+const SEAL_MAGIC: &[u8; 8] = b"OVAHSEA1";
+
+/// Size of the plaintext chunks that sealed files are split into. Every chunk
+/// but the last one is exactly this size.
+const SEAL_CHUNK_SIZE: usize = 4096;
+
+/// Size of the authentication tag that AES256-GCM appends to every ciphertext.
+const TAG_SIZE: usize = 16;
+
+/// Size of every encrypted chunk of a sealed file but the last one: its nonce,
+/// followed by the ciphertext and the authentication tag.
+const SEALED_CHUNK_SIZE: usize = NONCE_SIZE + SEAL_CHUNK_SIZE + TAG_SIZE;
+
+/// Encrypt a whole file, as a sealed file.
+///
+/// A sealed file is `SEAL_MAGIC` followed by the plaintext encrypted in chunks
+/// of `SEAL_CHUNK_SIZE` bytes, each with its own randomly generated nonce
+/// (written before it). The index of each chunk, and whether it is the last
+/// one, are authenticated along with it, so that chunks can't be reordered,
+/// dropped or truncated without `open_sealed` noticing.
+pub fn seal(plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+    let cipher = create_cipher()?;
+    let chunks = plaintext.len().div_ceil(SEAL_CHUNK_SIZE).max(1);
+    let mut sealed =
+        Vec::with_capacity(SEAL_MAGIC.len() + plaintext.len() + chunks * (NONCE_SIZE + TAG_SIZE));
+    sealed.extend_from_slice(SEAL_MAGIC);
+    for index in 0..chunks {
+        let start = index * SEAL_CHUNK_SIZE;
+        let end = plaintext.len().min(start + SEAL_CHUNK_SIZE);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = chunk_aad(index, index + 1 == chunks);
+        let payload = Payload {
+            msg: &plaintext[start..end],
+            aad: &aad,
+        };
+        let ciphertext = cipher.encrypt(&nonce, payload).map_err(|_| Error::Cipher)?;
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+    }
+    Ok(sealed)
+}
+
+/// Decrypt a sealed file (see `seal`), chunk by chunk, into a single
+/// plaintext. Fails if any of the chunks can't be decrypted.
+pub fn open_sealed(sealed: &[u8]) -> Result<Vec<u8>, Error> {
+    let chunks = sealed.strip_prefix(SEAL_MAGIC).ok_or(Error::NotSealed)?;
+    let cipher = create_cipher()?;
+    let count = chunks.len().div_ceil(SEALED_CHUNK_SIZE).max(1);
+    let mut plaintext = Vec::with_capacity(chunks.len());
+    for index in 0..count {
+        let start = index * SEALED_CHUNK_SIZE;
+        let chunk = &chunks[start..chunks.len().min(start + SEALED_CHUNK_SIZE)];
+        if chunk.len() < NONCE_SIZE + TAG_SIZE {
+            return Err(Error::Cipher);
+        }
+        let (nonce, ciphertext) = chunk.split_at(NONCE_SIZE);
+        let aad = chunk_aad(index, index + 1 == count);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &aad,
+        };
+        let decrypted = cipher
+            .decrypt(nonce.into(), payload)
+            .map_err(|_| Error::Cipher)?;
+        plaintext.extend_from_slice(&decrypted);
+    }
+    Ok(plaintext)
+}
+
+/// Additional authenticated data of the chunk at `index` of a sealed file.
+fn chunk_aad(index: usize, last: bool) -> [u8; 9] {
+    let mut aad = [0u8; 9];
+    aad[..8].copy_from_slice(&(index as u64).to_be_bytes());
+    aad[8] = u8::from(last);
+    aad
+}
+
 /// Create a AES-GCM cipher with a 256-bit key and 96-bit nonce for symmetric
 /// key encryption/decryption. Intialized with a key generated from the
 /// application identifier and the machine identifier.
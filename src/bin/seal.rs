@@ -0,0 +1,51 @@
+//! Encrypts a file (e.g., a private key, or a whole OpenVPN profile) so that
+//! the hook can serve it decrypted to OpenVPN (see `ENCRYPTED_FILES`). With
+//! `--open`, decrypts it back instead (e.g., to edit the profile).
+//!
+//! Like the embedded secrets, the file is encrypted with a key generated from
+//! the application identifier and the machine identifier, so it must be
+//! sealed on the machine where OpenVPN runs, with a build of the same
+//! application identifier as the hook.
+
+use std::{env, fs, io::Write, os::unix::fs::OpenOptionsExt, process::ExitCode};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (open, input, output) = match args.as_slice() {
+        [input, output] => (false, input, output),
+        [flag, input, output] if flag == "--open" => (true, input, output),
+        _ => {
+            eprintln!("Usage: openvpn-auth-hook-seal [--open] <input file> <output file>");
+            return ExitCode::FAILURE;
+        }
+    };
+    match convert(open, input, output) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Write the sealed (or, if `open`, the decrypted) contents of `input` to
+/// `output`, which is only readable and writable by its owner if it is
+/// created.
+fn convert(open: bool, input: &str, output: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = fs::read(input).map_err(|err| format!("Unable to read '{input}': {err}"))?;
+    let converted = if open {
+        encryption::open_sealed(&contents)
+    } else {
+        encryption::seal(&contents)
+    }
+    .map_err(|err| format!("Unable to convert '{input}': {err}"))?;
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(output)
+        .and_then(|mut file| file.write_all(&converted))
+        .map_err(|err| format!("Unable to write '{output}': {err}"))?;
+    Ok(())
+}
@@ -0,0 +1,324 @@
+//! A parser for OpenVPN 2.x options, in configuration files and on the
+//! command line, enough to find the files with credentials that they name
+//! (see `parse_command_line`) and the inline blocks of configuration files
+//! (e.g., `<auth-user-pass>`). It follows the rules of OpenVPN's own parser
+//! (`parse_argv`, `parse_line` and `read_inline_file` in `options.c`).
+//!
+//! Every line of a configuration file is split into tokens separated by
+//! whitespace. Tokens can be quoted with double quotes (where `\` escapes the
+//! next character) or single quotes (taken literally), and `\` escapes the
+//! next character outside of quotes too. A `#` or `;` at the start of a token
+//! comments out the rest of the line.
+//!
+//! A line is a directive, named after its first token (with or without a
+//! leading `--`, like on the command line). A line with a single `<tag>`
+//! token opens an inline block instead, which spans every following line up
+//! to the one starting with `</tag>` (after any leading whitespace). The lines
+//! inside a block are not tokenized, so they can't open blocks of their own.
+//!
+//! On the command line, every argument starting with `--` is an option, which
+//! takes the arguments that follow it (up to the next option) as parameters.
+//!
+//! The module doesn't depend on the rest of the crate, so that the tests can
+//! check it directly against the sample configurations in `tests/configs`.
+
+use std::{
+    ffi::OsStr,
+    fs,
+    ops::Range,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+/// How many `config` files can be nested, like in OpenVPN.
+const MAX_CONFIG_DEPTH: usize = 10;
+
+/// Parameters that some of the directives take instead of a file.
+const NOT_FILES: [&[u8]; 3] = [b"stdin", b"auto", b"auto-nct"];
+
+/// The directives that name a file with credentials.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CredentialKind {
+    /// The file of `auth-user-pass`.
+    AuthUserPass,
+    /// A configuration file with an `<auth-user-pass>` inline block.
+    InlineAuthUserPass,
+    /// The file of `askpass`.
+    Askpass,
+    /// The authfile of `http-proxy`.
+    HttpProxy,
+    /// The authfile of `socks-proxy`.
+    SocksProxy,
+}
+
+/// A file with credentials named in the options.
+pub struct CredentialFile {
+    /// Which directive names it.
+    pub kind: CredentialKind,
+    /// Its path exactly as given, which is the one that OpenVPN opens.
+    pub path: Vec<u8>,
+    /// Its absolute path, taking `--cd` into account.
+    pub absolute_path: PathBuf,
+}
+
+/// What `parse_command_line` found.
+#[derive(Default)]
+pub struct Options {
+    /// The files with credentials, in the order they are named (where the
+    /// last one of each kind wins, for OpenVPN).
+    pub credential_files: Vec<CredentialFile>,
+    /// The problems found on the way (e.g., configuration files that can't be
+    /// read), which don't stop the parsing.
+    pub errors: Vec<String>,
+}
+
+/// Parse the command line arguments `args` (without the name of the program)
+/// of OpenVPN, started in the `cwd` directory, following every `--config`
+/// file (and the files that they include with `config` in turn).
+///
+/// OpenVPN changes to the `--cd` directory as soon as it parses that option,
+/// so configuration files are read relative to the `--cd` directory given
+/// before them, while the files with credentials (opened once all the options
+/// have been parsed) are relative to the last one.
+pub fn parse_command_line(args: &[&[u8]], cwd: &Path) -> Options {
+    let mut parser = Parser {
+        dir: cwd.to_owned(),
+        files: Vec::new(),
+        errors: Vec::new(),
+    };
+    match args {
+        // `openvpn <file>` is short for `openvpn --config <file>`
+        [file] if !file.starts_with(b"--") => parser.directive(&[b"config", file], 0),
+        _ => {
+            for option in command_line_options(args) {
+                parser.directive(&option, 0);
+            }
+        }
+    }
+    let Parser { dir, files, errors } = parser;
+    Options {
+        credential_files: files
+            .into_iter()
+            .map(|(kind, path, absolute_path)| CredentialFile {
+                kind,
+                absolute_path: absolute_path.unwrap_or_else(|| resolve(&dir, &path)),
+                path,
+            })
+            .collect(),
+        errors,
+    }
+}
+
+/// The options in the command line arguments `args`, each with its name
+/// (without the leading `--`) and its parameters. Arguments before the first
+/// option are skipped.
+fn command_line_options<'a>(args: &[&'a [u8]]) -> Vec<Vec<&'a [u8]>> {
+    let mut options: Vec<Vec<&[u8]>> = Vec::new();
+    for arg in args {
+        match arg.strip_prefix(b"--") {
+            Some(name) => options.push(vec![name]),
+            None => {
+                if let Some(option) = options.last_mut() {
+                    option.push(arg);
+                }
+            }
+        }
+    }
+    options
+}
+
+/// State of `parse_command_line`.
+struct Parser {
+    /// The current directory of OpenVPN at this point of the options.
+    dir: PathBuf,
+    /// The files with credentials found so far, with their absolute path if
+    /// it is already known (i.e., for configuration files, which are opened
+    /// right away).
+    files: Vec<(CredentialKind, Vec<u8>, Option<PathBuf>)>,
+    /// The problems found so far.
+    errors: Vec<String>,
+}
+
+impl Parser {
+    /// Take the directive `tokens` (its name and parameters) into account.
+    /// `depth` is how many `config` files it is nested in.
+    fn directive(&mut self, tokens: &[&[u8]], depth: usize) {
+        let (kind, index) = match tokens.first().copied() {
+            Some(b"cd") => {
+                if let Some(dir) = tokens.get(1) {
+                    self.dir = resolve(&self.dir, dir);
+                }
+                return;
+            }
+            Some(b"config") => {
+                if let Some(file) = tokens.get(1) {
+                    self.config(file, depth + 1);
+                }
+                return;
+            }
+            Some(b"auth-user-pass") => (CredentialKind::AuthUserPass, 1),
+            Some(b"askpass") => (CredentialKind::Askpass, 1),
+            Some(b"http-proxy") => (CredentialKind::HttpProxy, 3),
+            Some(b"socks-proxy") => (CredentialKind::SocksProxy, 3),
+            _ => return,
+        };
+        if let Some(file) = tokens.get(index).filter(|file| !NOT_FILES.contains(file)) {
+            self.files.push((kind, file.to_vec(), None));
+        }
+    }
+
+    /// Take the directives of the configuration file `file` into account.
+    /// `depth` is how many `config` files it is nested in, counting itself.
+    fn config(&mut self, file: &[u8], depth: usize) {
+        if depth > MAX_CONFIG_DEPTH {
+            self.errors.push(format!(
+                "Too many nested configuration files. Ignoring '{}'",
+                String::from_utf8_lossy(file)
+            ));
+            return;
+        }
+        let path = resolve(&self.dir, file);
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                self.errors.push(format!(
+                    "Unable to read the configuration file '{}': {err}",
+                    path.display()
+                ));
+                return;
+            }
+        };
+        let (items, unterminated) = items(&contents);
+        for item in items {
+            match item {
+                Item::Directive(tokens) => {
+                    let tokens: Vec<&[u8]> = tokens.iter().map(Vec::as_slice).collect();
+                    self.directive(&tokens, depth);
+                }
+                Item::Block { tag, .. } if tag == b"auth-user-pass" => self.files.push((
+                    CredentialKind::InlineAuthUserPass,
+                    file.to_vec(),
+                    Some(path.clone()),
+                )),
+                Item::Block { .. } => {}
+            }
+        }
+        if let Some(tag) = unterminated {
+            self.errors.push(format!(
+                "Unterminated <{}> block in the configuration file '{}'",
+                String::from_utf8_lossy(&tag),
+                path.display()
+            ));
+        }
+    }
+}
+
+/// Where the file `path` is, relative to the `dir` directory.
+fn resolve(dir: &Path, path: &[u8]) -> PathBuf {
+    dir.join(Path::new(OsStr::from_bytes(path)))
+}
+
+/// An item of a configuration file.
+pub enum Item {
+    /// A directive: its name (without any leading `--`) and its parameters.
+    Directive(Vec<Vec<u8>>),
+    /// An inline block: its tag, and the byte range of its content (i.e., of
+    /// the lines between `<tag>` and `</tag>`).
+    Block { tag: Vec<u8>, content: Range<usize> },
+}
+
+/// The directives and inline blocks of `config`, in order, up to the first
+/// unterminated inline block (which is an error for OpenVPN), whose tag is
+/// returned too.
+pub fn items(config: &[u8]) -> (Vec<Item>, Option<Vec<u8>>) {
+    let mut items = Vec::new();
+    let mut lines = lines(config);
+    while let Some(line) = lines.next() {
+        let mut tokens = tokenize(&config[line.clone()]);
+        let Some(tag) = opening_tag(&tokens) else {
+            if let Some(name) = tokens.first_mut() {
+                if let Some(stripped) = name.strip_prefix(b"--") {
+                    *name = stripped.to_vec();
+                }
+                items.push(Item::Directive(tokens));
+            }
+            continue;
+        };
+        let tag = tag.to_vec();
+        let closing_tag = [b"</", tag.as_slice(), b">"].concat();
+        let Some(closing_line) = lines.find(|line| {
+            config[line.clone()]
+                .trim_ascii_start()
+                .starts_with(&closing_tag)
+        }) else {
+            return (items, Some(tag));
+        };
+        items.push(Item::Block {
+            tag,
+            content: line.end..closing_line.start,
+        });
+    }
+    (items, None)
+}
+
+/// Byte range of the content of the first inline block named `tag` in
+/// `config`, if any.
+pub fn inline_block(config: &[u8], tag: &[u8]) -> Option<Range<usize>> {
+    items(config).0.into_iter().find_map(|item| match item {
+        Item::Block {
+            tag: block_tag,
+            content,
+        } if block_tag == tag => Some(content),
+        _ => None,
+    })
+}
+
+/// Byte ranges of the lines of `config`, each with its newline, if any.
+fn lines(config: &[u8]) -> impl Iterator<Item = Range<usize>> + '_ {
+    config
+        .split_inclusive(|&byte| byte == b'\n')
+        .scan(0, |start, line| {
+            let range = *start..*start + line.len();
+            *start = range.end;
+            Some(range)
+        })
+}
+
+/// The tokens of a line, without quotes, escapes and comments.
+fn tokenize(line: &[u8]) -> Vec<Vec<u8>> {
+    let mut tokens = Vec::new();
+    let mut token: Option<Vec<u8>> = None;
+    let mut quote = None;
+    let mut bytes = line.iter().copied();
+    while let Some(byte) = bytes.next() {
+        match (quote, byte) {
+            (Some(b'"'), b'"') | (Some(b'\''), b'\'') => quote = None,
+            (None | Some(b'"'), b'\\') => {
+                if let Some(escaped) = bytes.next() {
+                    token.get_or_insert_with(Vec::new).push(escaped);
+                }
+            }
+            (Some(_), _) => token.get_or_insert_with(Vec::new).push(byte),
+            (None, b'"' | b'\'') => {
+                quote = Some(byte);
+                token.get_or_insert_with(Vec::new);
+            }
+            (None, b'#' | b';') if token.is_none() => break,
+            (None, _) if byte.is_ascii_whitespace() => tokens.extend(token.take()),
+            (None, _) => token.get_or_insert_with(Vec::new).push(byte),
+        }
+    }
+    tokens.extend(token);
+    tokens
+}
+
+/// The name of the inline block that a line with `tokens` opens, if any.
+fn opening_tag(tokens: &[Vec<u8>]) -> Option<&[u8]> {
+    match tokens {
+        [token] => token
+            .strip_prefix(b"<")?
+            .strip_suffix(b">")
+            .filter(|tag| !tag.is_empty() && !tag.starts_with(b"/")),
+        _ => None,
+    }
+}
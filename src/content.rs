@@ -0,0 +1,359 @@
+//! Virtual content of the tracked streams.
+//!
+//! The first time a tracked stream is read with anything other than `fgets`
+//! (e.g., `fread` into a block buffer, or `getc` char by char), or when
+//! `fgets` reaches the first replaced line, the rest of the file is read in one
+//! go through the original functions and rewritten in memory, with the password
+//! line (and the username line, if the secret has a username) replaced. From
+//! then on, every read from that stream (whatever the function used) is served
+//! from the rewritten copy instead of from the file.
+//!
+//! Tracked file descriptors work the same way, except that there is no `fgets`
+//! equivalent for them, so their content is always loaded on the first read.
+//!
+//! Encrypted files (see `params::AuthFile::encrypted`) are loaded on the
+//! first read too, whatever the function used, and decrypted as a whole
+//! before being rewritten (if they have credentials at all). So are
+//! configuration files (see `params::AuthFileKind::Config`), where only the
+//! lines inside their `<auth-user-pass>` block are replaced.
+//!
+//! The underlying stream is left at EOF after loading its content, so its EOF
+//! indicator is managed by hand to keep `feof` consistent with the virtual
+//! content: it is cleared after loading and set again once a read goes past
+//! the end of the virtual content.
+
+use std::{ffi::CString, io, ptr};
+
+use libc::{c_char, c_int, c_void, FILE};
+
+use crate::{
+    config,
+    functions::Functions,
+    params::{self, AuthFile, AuthFileKind, MissingPasswordPolicy},
+};
+
+/// Name of the inline block of the configuration files with the credentials.
+const INLINE_AUTH_TAG: &[u8] = b"auth-user-pass";
+
+/// Size of the chunks in which the underlying stream is read when loading its
+/// content.
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// In-memory copy of the (rewritten) content of a tracked stream, together
+/// with the current read position.
+pub struct Content {
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl Content {
+    /// Read the rest of `stream` and rewrite it, replacing the password line
+    /// (and the username line, if any) with the secret of `auth_file`.
+    ///
+    /// `lines_read` is the number of complete lines that were already read
+    /// from `stream` (with `fgets`) before loading the rest of its content.
+    /// `mid_line` is whether part of the next line was read too.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be a valid pointer to a FILE, not yet closed.
+    pub unsafe fn load(
+        stream: *mut FILE,
+        lines_read: usize,
+        mid_line: bool,
+        auth_file: &AuthFile,
+    ) -> Self {
+        let content = Self::new(read_to_end(stream), lines_read, mid_line, auth_file);
+        if !content.is_empty() {
+            libc::clearerr(stream);
+        }
+        content
+    }
+
+    /// Read the rest of the file referred to by `fd` (a file descriptor) and
+    /// rewrite it, replacing the password line (and the username line, if any)
+    /// with the secret of `auth_file`.
+    pub fn load_descriptor(fd: c_int, auth_file: &AuthFile) -> Self {
+        Self::new(read_descriptor_to_end(fd), 0, false, auth_file)
+    }
+
+    fn new(original: Vec<u8>, lines_read: usize, mid_line: bool, auth_file: &AuthFile) -> Self {
+        let original = if auth_file.encrypted {
+            match decrypt(&original, auth_file) {
+                Some(decrypted) => decrypted,
+                None => {
+                    return Content {
+                        bytes: original,
+                        position: 0,
+                    }
+                }
+            }
+        } else {
+            original
+        };
+        let bytes = match auth_file.kind {
+            AuthFileKind::Opaque => original,
+            AuthFileKind::Config => rewrite_inline_block(original, auth_file),
+            _ => rewrite_auth_lines(original, lines_read, mid_line, auth_file),
+        };
+        Content { bytes, position: 0 }
+    }
+
+    /// Whether the content is empty.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Current read position, in bytes from the beginning of the content.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Move the read position to `position` bytes from the beginning of the
+    /// content (or to the end, if it is past the end).
+    pub fn seek(&mut self, position: usize) {
+        self.position = position.min(self.bytes.len());
+    }
+
+    /// Push `byte` back, the way `ungetc` does, so that it is the next byte
+    /// read.
+    pub fn unread(&mut self, byte: u8) {
+        match self.position.checked_sub(1) {
+            Some(position) => {
+                self.position = position;
+                self.bytes[position] = byte;
+            }
+            None => self.bytes.insert(0, byte),
+        }
+    }
+
+    /// The content that has not been read yet.
+    pub fn remaining(&self) -> &[u8] {
+        &self.bytes[self.position..]
+    }
+
+    /// Mark `n` bytes of the remaining content as read.
+    pub fn consume(&mut self, n: usize) {
+        self.position = self.position.saturating_add(n).min(self.bytes.len());
+    }
+
+    /// Read as many bytes as possible into `buf`. Returns the number of bytes
+    /// read.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let n = buf.len().min(self.remaining().len());
+        buf[..n].copy_from_slice(&self.remaining()[..n]);
+        self.consume(n);
+        n
+    }
+
+    /// Read as many bytes as possible into `buf`, starting at `offset` (from the
+    /// beginning of the content) instead of at the current position, which is
+    /// left untouched. Returns the number of bytes read.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let available = self.bytes.get(offset..).unwrap_or_default();
+        let n = buf.len().min(available.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        n
+    }
+
+    /// Read the next byte, if any.
+    pub fn read_byte(&mut self) -> Option<u8> {
+        let byte = self.peek_byte();
+        self.consume(1);
+        byte
+    }
+
+    /// Return the next byte, if any, without consuming it.
+    pub fn peek_byte(&self) -> Option<u8> {
+        self.remaining().first().copied()
+    }
+
+    /// Read bytes up to and including `delim`, but no more than `limit` bytes.
+    /// The result doesn't end in `delim` if the end of the content or the
+    /// limit is reached first.
+    pub fn read_until(&mut self, delim: u8, limit: usize) -> &[u8] {
+        let start = self.position;
+        let remaining = &self.remaining()[..self.remaining().len().min(limit)];
+        let len = match remaining.iter().position(|&byte| byte == delim) {
+            Some(index) => index + 1,
+            None => remaining.len(),
+        };
+        self.consume(len);
+        &self.bytes[start..start + len]
+    }
+
+    /// Read a line into `buf` the way `fgets` does: at most `size - 1` bytes,
+    /// stopping after a newline, and always followed by a null byte. Returns
+    /// whether anything was read (i.e., whether `fgets` should succeed) and
+    /// whether the end of the content was reached.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must be valid for writes of `size` bytes.
+    pub unsafe fn read_line(&mut self, buf: *mut c_char, size: usize) -> (bool, bool) {
+        if size == 0 {
+            return (false, false);
+        }
+        let line = self.read_until(b'\n', size - 1);
+        let hit_eof = line.len() < size - 1 && line.last() != Some(&b'\n');
+        if line.is_empty() && size > 1 {
+            return (false, hit_eof);
+        }
+        ptr::copy_nonoverlapping(line.as_ptr(), buf.cast(), line.len());
+        *buf.add(line.len()) = 0;
+        (true, hit_eof)
+    }
+}
+
+/// Replace the password line (and the username line, if any) of `original`
+/// with the secret of `auth_file`. See `rewrite` for `lines_read` and
+/// `mid_line`. If the secret can't be obtained, `original` is served as is.
+fn rewrite_auth_lines(
+    original: Vec<u8>,
+    lines_read: usize,
+    mid_line: bool,
+    auth_file: &AuthFile,
+) -> Vec<u8> {
+    let password_line = match params::password_line(auth_file.secret) {
+        Ok(password_line) => password_line,
+        Err(err) => {
+            error!("Unexpected error obtaining the password: {err}");
+            return original;
+        }
+    };
+    let mut replacements = vec![(auth_file.password_line(), password_line)];
+    if let Some(line_number) = auth_file.username_line() {
+        match params::username_line(auth_file.secret) {
+            Ok(Some(username_line)) => replacements.push((line_number, username_line)),
+            Ok(None) => {}
+            Err(err) => {
+                error!("Unexpected error obtaining the username: {err}")
+            }
+        }
+    }
+    let (mut bytes, lines) = rewrite(&original, lines_read, mid_line, &replacements);
+    if *params::AUTH_FILE_MISSING_PASSWORD == MissingPasswordPolicy::Append
+        && lines + 1 == auth_file.password_line()
+    {
+        // The file ends right before the password line (e.g., after the
+        // username line), so the password line is made up. If the last
+        // line has no trailing newline, it gets one, unless it has already
+        // been read (in which case `fgets` already returned it as a line
+        // of its own)
+        if bytes.last().is_some_and(|&byte| byte != b'\n') {
+            bytes.push(b'\n');
+        }
+        bytes.extend_from_slice(replacements[0].1.as_bytes());
+    }
+    bytes
+}
+
+/// Replace the password line (and the username line, if any) inside the
+/// `<auth-user-pass>` block of the configuration file `auth_file`, given its
+/// whole `original` content. Everything outside the block is left as is.
+fn rewrite_inline_block(original: Vec<u8>, auth_file: &AuthFile) -> Vec<u8> {
+    let Some(block) = config::inline_block(&original, INLINE_AUTH_TAG) else {
+        warning!(
+            "No <auth-user-pass> block in the configuration file '{}'",
+            auth_file.path
+        );
+        return original;
+    };
+    let mut bytes = original[..block.start].to_vec();
+    bytes.extend(rewrite_auth_lines(
+        original[block.clone()].to_vec(),
+        0,
+        false,
+        auth_file,
+    ));
+    bytes.extend_from_slice(&original[block.end..]);
+    bytes
+}
+
+/// The decrypted content of the encrypted file `auth_file`, given its whole
+/// `original` content, if it can be decrypted. If not, it is served as is.
+///
+/// The file is decrypted whole, the first time it is read, and the plaintext
+/// is then held in memory like any other virtual content.
+fn decrypt(original: &[u8], auth_file: &AuthFile) -> Option<Vec<u8>> {
+    encryption::open_sealed(original)
+        .map_err(|err| {
+            error!(
+                "Unable to decrypt the encrypted file '{}': {err}",
+                auth_file.path
+            );
+        })
+        .ok()
+}
+
+/// Set the EOF indicator of `stream`, so that `feof` reports the end of the
+/// virtual content. The underlying stream is already at EOF after loading its
+/// content, so reading from it again sets the indicator.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+pub unsafe fn set_eof_indicator(stream: *mut FILE) {
+    Functions::fgetc(stream);
+}
+
+/// Read everything that is left in `stream`, with the original functions.
+unsafe fn read_to_end(stream: *mut FILE) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut chunk = [0u8; READ_CHUNK_SIZE];
+    loop {
+        let n = Functions::fread(chunk.as_mut_ptr() as *mut c_void, 1, chunk.len(), stream);
+        if n == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&chunk[..n]);
+    }
+    bytes
+}
+
+/// Read everything that is left in the file referred to by `fd`, with the
+/// original functions.
+fn read_descriptor_to_end(fd: c_int) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut chunk = [0u8; READ_CHUNK_SIZE];
+    loop {
+        let n = Functions::read(fd, chunk.as_mut_ptr() as *mut c_void, chunk.len());
+        match n {
+            0 => break,
+            n if n > 0 => bytes.extend_from_slice(&chunk[..n as usize]),
+            _ if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            _ => break,
+        }
+    }
+    bytes
+}
+
+/// Replace the lines of `original` given in `replacements`, as pairs of line
+/// number and replacement line. Returns the rewritten bytes, and the number of
+/// lines in the whole file.
+///
+/// `original` starts right after `lines_read` complete lines of the file (in
+/// the middle of the next one if `mid_line`). Like with
+/// `fgets`, each replacement line includes its own trailing newline even if
+/// the original line didn't have one.
+fn rewrite(
+    original: &[u8],
+    lines_read: usize,
+    mid_line: bool,
+    replacements: &[(usize, CString)],
+) -> (Vec<u8>, usize) {
+    let mut rewritten = Vec::with_capacity(original.len());
+    let mut lines = 0;
+    for (index, line) in original.split_inclusive(|&byte| byte == b'\n').enumerate() {
+        let line_number = lines_read + index + 1;
+        match replacements
+            .iter()
+            .find(|(number, _)| *number == line_number)
+        {
+            Some((_, replacement)) => rewritten.extend_from_slice(replacement.as_bytes()),
+            None => rewritten.extend_from_slice(line),
+        }
+        lines = index + 1;
+    }
+    (rewritten, lines_read + lines.max(usize::from(mid_line)))
+}
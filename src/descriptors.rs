@@ -0,0 +1,408 @@
+//! Replacements for the file descriptor functions (`open`, `read`, `close`,
+//! etc.).
+//!
+//! Some clients never use stdio to read the `auth-user-pass` file. C++
+//! programs using `std::ifstream` (e.g., openvpn3-linux) and Rust programs
+//! `open` and `read` it directly. The file is tracked by file descriptor in
+//! that case, and reads are served from its virtual content (see the `content`
+//! module), exactly like for tracked streams. `lseek` moves the read position
+//! in the virtual content, so the file can be read again from any position.
+//!
+//! `open` and `openat` are variadic in C, but their only optional argument
+//! (`mode`) is an integer that is passed in a register both in x86-64 and
+//! AArch64 (Linux), exactly like a regular argument. So they can be replaced
+//! by functions that always take it (its value is just garbage when the caller
+//! didn't pass it, but it is then also ignored by the original function).
+
+use std::slice;
+
+use libc::{
+    c_char, c_int, c_void, mode_t, off64_t, off_t, size_t, ssize_t, AT_FDCWD, O_ACCMODE, O_RDONLY,
+    O_RDWR, SEEK_CUR, SEEK_END, SEEK_SET,
+};
+
+use crate::{
+    functions::Functions, guard, is_tracked_access, matching, memory_files, params::AuthFile,
+    state::State, Access,
+};
+
+/// Replacement for the `open` libc function.
+///
+/// If the file being opened is the `auth-user-pass` file, it tracks the file
+/// descriptor so that reads from it are served from its virtual content.
+///
+/// # Safety
+///
+/// `path` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn open(path: *const c_char, flags: c_int, mode: mode_t) -> c_int {
+    track_opened("open", AT_FDCWD, path, flags, |path| {
+        Functions::open(path, flags, mode)
+    })
+}
+
+/// Replacement for the `open64` libc function. See `open`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn open64(path: *const c_char, flags: c_int, mode: mode_t) -> c_int {
+    track_opened("open64", AT_FDCWD, path, flags, |path| {
+        Functions::open64(path, flags, mode)
+    })
+}
+
+/// Replacement for the `__open_2` glibc function, the `-D_FORTIFY_SOURCE`
+/// variant of `open`. See `open`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn __open_2(path: *const c_char, flags: c_int) -> c_int {
+    track_opened("__open_2", AT_FDCWD, path, flags, |path| {
+        Functions::open_2(path, flags)
+    })
+}
+
+/// Replacement for the `__open64_2` glibc function, the `-D_FORTIFY_SOURCE`
+/// variant of `open64`. See `open`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn __open64_2(path: *const c_char, flags: c_int) -> c_int {
+    track_opened("__open64_2", AT_FDCWD, path, flags, |path| {
+        Functions::open64_2(path, flags)
+    })
+}
+
+/// Replacement for the `openat` libc function. See `open`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn openat(
+    dirfd: c_int,
+    path: *const c_char,
+    flags: c_int,
+    mode: mode_t,
+) -> c_int {
+    track_opened("openat", dirfd, path, flags, |path| {
+        Functions::openat(dirfd, path, flags, mode)
+    })
+}
+
+/// Replacement for the `openat64` libc function. See `open`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn openat64(
+    dirfd: c_int,
+    path: *const c_char,
+    flags: c_int,
+    mode: mode_t,
+) -> c_int {
+    track_opened("openat64", dirfd, path, flags, |path| {
+        Functions::openat64(dirfd, path, flags, mode)
+    })
+}
+
+/// Replacement for the `__openat_2` glibc function, the `-D_FORTIFY_SOURCE`
+/// variant of `openat`. See `open`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn __openat_2(dirfd: c_int, path: *const c_char, flags: c_int) -> c_int {
+    track_opened("__openat_2", dirfd, path, flags, |path| {
+        Functions::openat_2(dirfd, path, flags)
+    })
+}
+
+/// Replacement for the `__openat64_2` glibc function, the `-D_FORTIFY_SOURCE`
+/// variant of `openat64`. See `open`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn __openat64_2(dirfd: c_int, path: *const c_char, flags: c_int) -> c_int {
+    track_opened("__openat64_2", dirfd, path, flags, |path| {
+        Functions::openat64_2(dirfd, path, flags)
+    })
+}
+
+/// Replacement for the `read` libc function.
+///
+/// If the file descriptor is tracked, the data is read from its virtual
+/// content instead of from the file.
+///
+/// # Safety
+///
+/// `buf` must be valid for writes of `count` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn read(fd: c_int, buf: *mut c_void, count: size_t) -> ssize_t {
+    guard::or_original(
+        "read",
+        || read_virtual(fd, buf, count),
+        || Functions::read(fd, buf, count),
+    )
+}
+
+/// Replacement for the `__read_chk` glibc function, the `-D_FORTIFY_SOURCE`
+/// variant of `read`.
+///
+/// # Safety
+///
+/// `buf` must be valid for writes of `buflen` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn __read_chk(
+    fd: c_int,
+    buf: *mut c_void,
+    count: size_t,
+    buflen: size_t,
+) -> ssize_t {
+    if count > buflen {
+        // Let the original function report the buffer overflow
+        return Functions::read_chk(fd, buf, count, buflen);
+    }
+    guard::or_original(
+        "__read_chk",
+        || read_virtual(fd, buf, count),
+        || Functions::read_chk(fd, buf, count, buflen),
+    )
+}
+
+/// Replacement for the `pread` libc function.
+///
+/// If the file descriptor is tracked, the data is read from its virtual
+/// content instead of from the file.
+///
+/// # Safety
+///
+/// `buf` must be valid for writes of `count` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn pread(
+    fd: c_int,
+    buf: *mut c_void,
+    count: size_t,
+    offset: off_t,
+) -> ssize_t {
+    guard::or_original(
+        "pread",
+        || pread_virtual(fd, buf, count, offset),
+        || Functions::pread(fd, buf, count, offset),
+    )
+}
+
+/// Replacement for the `pread64` libc function. See `pread`.
+///
+/// # Safety
+///
+/// `buf` must be valid for writes of `count` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn pread64(
+    fd: c_int,
+    buf: *mut c_void,
+    count: size_t,
+    offset: off64_t,
+) -> ssize_t {
+    guard::or_original(
+        "pread64",
+        || pread_virtual(fd, buf, count, offset),
+        || Functions::pread64(fd, buf, count, offset),
+    )
+}
+
+/// Replacement for the `__pread_chk` glibc function, the `-D_FORTIFY_SOURCE`
+/// variant of `pread`.
+///
+/// # Safety
+///
+/// `buf` must be valid for writes of `buflen` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn __pread_chk(
+    fd: c_int,
+    buf: *mut c_void,
+    count: size_t,
+    offset: off_t,
+    buflen: size_t,
+) -> ssize_t {
+    if count > buflen {
+        // Let the original function report the buffer overflow
+        return Functions::pread_chk(fd, buf, count, offset, buflen);
+    }
+    guard::or_original(
+        "__pread_chk",
+        || pread_virtual(fd, buf, count, offset),
+        || Functions::pread_chk(fd, buf, count, offset, buflen),
+    )
+}
+
+/// Replacement for the `__pread64_chk` glibc function, the
+/// `-D_FORTIFY_SOURCE` variant of `pread64`.
+///
+/// # Safety
+///
+/// `buf` must be valid for writes of `buflen` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn __pread64_chk(
+    fd: c_int,
+    buf: *mut c_void,
+    count: size_t,
+    offset: off64_t,
+    buflen: size_t,
+) -> ssize_t {
+    if count > buflen {
+        // Let the original function report the buffer overflow
+        return Functions::pread64_chk(fd, buf, count, offset, buflen);
+    }
+    guard::or_original(
+        "__pread64_chk",
+        || pread_virtual(fd, buf, count, offset),
+        || Functions::pread64_chk(fd, buf, count, offset, buflen),
+    )
+}
+
+/// Replacement for the `lseek` libc function.
+///
+/// If the file descriptor is tracked, the read position in its virtual
+/// content is moved instead of the position in the file.
+#[no_mangle]
+pub extern "C" fn lseek(fd: c_int, offset: off_t, whence: c_int) -> off_t {
+    guard::or_original(
+        "lseek",
+        || seek_virtual(fd, offset, whence),
+        || Functions::lseek(fd, offset, whence),
+    )
+}
+
+/// Replacement for the `lseek64` libc function, the large file variant of
+/// `lseek`. See `lseek`.
+#[no_mangle]
+pub extern "C" fn lseek64(fd: c_int, offset: off64_t, whence: c_int) -> off64_t {
+    guard::or_original(
+        "lseek64",
+        || seek_virtual(fd, offset, whence),
+        || Functions::lseek64(fd, offset, whence),
+    )
+}
+
+/// Replacement for the `close` libc function.
+///
+/// If the file descriptor is tracked, it stops being tracked.
+#[no_mangle]
+pub extern "C" fn close(fd: c_int) -> c_int {
+    guard::catch_panic("close", || State::remove_descriptor(fd));
+    Functions::close(fd)
+}
+
+/// Common logic for the `open` family of replacements (`name` is the name of
+/// the replacement). `original` calls the original function with the path to
+/// open (`path`, unless it is an in-memory auth file). The resulting file
+/// descriptor is tracked if it refers to an auth file and was opened for
+/// reading (see `is_tracked_access`).
+unsafe fn track_opened(
+    name: &str,
+    dirfd: c_int,
+    path: *const c_char,
+    flags: c_int,
+    original: impl FnOnce(*const c_char) -> c_int,
+) -> c_int {
+    let fd = memory_files::with_path(name, dirfd, path, original);
+    guard::catch_panic(name, || {
+        if fd == -1 || !is_tracked_access(flags_access(flags)) {
+            return;
+        }
+        if let Some(auth_file) = auth_file_at(dirfd, path, fd) {
+            State::add_descriptor(fd, auth_file);
+        }
+    });
+    fd
+}
+
+/// Access granted to a file descriptor opened with `flags`.
+fn flags_access(flags: c_int) -> Access {
+    match flags & O_ACCMODE {
+        O_RDONLY => Access::ReadOnly,
+        O_RDWR => Access::ReadWrite,
+        _ => Access::WriteOnly,
+    }
+}
+
+/// The auth file that `path` (relative to the `dirfd` directory) is, if any.
+/// A path relative to a directory other than the current one can't be
+/// compared with the auth file paths, so the already opened `fd` is compared
+/// by identity instead.
+unsafe fn auth_file_at(dirfd: c_int, path: *const c_char, fd: c_int) -> Option<&'static AuthFile> {
+    if dirfd == AT_FDCWD || *path == b'/' as c_char {
+        matching::auth_file(path, fd)
+    } else {
+        matching::auth_file_descriptor(fd)
+    }
+}
+
+/// Common logic for the `read` family of replacements. Returns `None` if the
+/// file descriptor is not tracked.
+unsafe fn read_virtual(fd: c_int, buf: *mut c_void, count: size_t) -> Option<ssize_t> {
+    State::with_descriptor_content(fd, |content| content.read(buffer(buf, count)) as ssize_t)
+}
+
+/// Common logic for the `pread` family of replacements. Returns `None` if the
+/// file descriptor is not tracked (or if the offset is invalid, so that the
+/// original function reports the error).
+unsafe fn pread_virtual(
+    fd: c_int,
+    buf: *mut c_void,
+    count: size_t,
+    offset: off64_t,
+) -> Option<ssize_t> {
+    let offset = offset.try_into().ok()?;
+    State::with_descriptor_content(fd, |content| {
+        content.read_at(offset, buffer(buf, count)) as ssize_t
+    })
+}
+
+/// Common logic for the `lseek` family of replacements. Returns `None` if the
+/// file descriptor is not tracked. Positions past the end of the virtual
+/// content are accepted, but reading from them reads nothing.
+fn seek_virtual(fd: c_int, offset: off64_t, whence: c_int) -> Option<off64_t> {
+    State::with_descriptor_content(fd, |content| {
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => content.position(),
+            SEEK_END => content.position() + content.remaining().len(),
+            _ => return invalid_argument(),
+        };
+        let Some(target) = (base as off64_t)
+            .checked_add(offset)
+            .and_then(|target| usize::try_from(target).ok())
+        else {
+            return invalid_argument();
+        };
+        content.seek(target);
+        target as off64_t
+    })
+}
+
+/// Set `errno` to `EINVAL` and return the error value of the `lseek` family.
+fn invalid_argument() -> off64_t {
+    unsafe { *libc::__errno_location() = libc::EINVAL };
+    -1
+}
+
+/// The buffer of `count` bytes at `buf`, as a slice.
+unsafe fn buffer<'a>(buf: *mut c_void, count: size_t) -> &'a mut [u8] {
+    if count == 0 {
+        &mut []
+    } else {
+        slice::from_raw_parts_mut(buf.cast(), count)
+    }
+}
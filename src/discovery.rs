@@ -0,0 +1,110 @@
+//! Discovery of the auth files from OpenVPN's own options (see
+//! `params::AUTH_FILE_DISCOVERY`), so that their paths don't need to be given
+//! by hand.
+//!
+//! The options are read from the command line of the process
+//! (`/proc/self/cmdline`), as long as it is OpenVPN, and parsed with the `config` module, which follows
+//! every `--config` file. The files are found in the `auth-user-pass`,
+//! `askpass`, `http-proxy` and `socks-proxy` directives, or the
+//! `<auth-user-pass>` inline block of a configuration file. Like for OpenVPN,
+//! the last directive of each kind wins.
+//!
+//! With `AUTH_FILE_MATCHING=path`, the paths of the discovered files are kept
+//! exactly as OpenVPN will open them, so that they match byte by byte.
+//! Otherwise, they are made absolute (taking `--cd` into account), so that
+//! they can be stat'ed from wherever the hook happens to be at the time.
+//!
+//! The hook is inherited by the processes that OpenVPN runs (e.g., its `up`
+//! and `down` scripts), whose command lines are not OpenVPN options. Nothing
+//! is discovered in them, and nothing is reported either.
+
+use std::{
+    env,
+    ffi::OsStr,
+    fs,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use once_cell::sync::Lazy;
+
+use crate::{
+    config::{self, CredentialKind},
+    params::{self, AuthFileKind, AuthFileMatching},
+};
+
+/// Name of the executable of OpenVPN.
+const OPENVPN: &str = "openvpn";
+
+/// The current directory of the process when the hook was loaded (see
+/// `init`), which relative `--cd` directories are relative to. By the time the
+/// auth files are needed, OpenVPN may have already changed to the `--cd`
+/// directory, which would then be applied twice.
+static STARTUP_DIR: Lazy<PathBuf> = Lazy::new(|| env::current_dir().unwrap_or_default());
+
+/// Record the current directory right away, before OpenVPN changes it.
+pub fn init() {
+    Lazy::force(&STARTUP_DIR);
+}
+
+/// The auth files found in OpenVPN's options.
+#[derive(Default)]
+pub struct Discovered {
+    /// The `auth-user-pass` file (`AuthFileKind::UserPass`), or the
+    /// configuration file with an `<auth-user-pass>` block
+    /// (`AuthFileKind::Config`).
+    pub auth_user_pass: Option<(AuthFileKind, String)>,
+    /// The `askpass` file.
+    pub askpass: Option<String>,
+    /// The authfile of the `http-proxy` or `socks-proxy` directives.
+    pub proxy: Option<(AuthFileKind, String)>,
+}
+
+/// Find the auth files in the options of the current process, or `None` if it
+/// is not OpenVPN. Nothing is found if the command line can't be read.
+pub fn discover() -> Option<Discovered> {
+    let mut discovered = Discovered::default();
+    let cmdline = match fs::read("/proc/self/cmdline") {
+        Ok(cmdline) => cmdline,
+        Err(err) => {
+            error!("Unable to read the command line of OpenVPN: {err}");
+            return Some(discovered);
+        }
+    };
+    let mut args = cmdline.split(|&byte| byte == 0);
+    if !is_openvpn(args.next().unwrap_or_default()) {
+        return None;
+    }
+    let args: Vec<&[u8]> = args.filter(|arg| !arg.is_empty()).collect();
+    let options = config::parse_command_line(&args, &STARTUP_DIR);
+    for error in options.errors {
+        error!("{error}");
+    }
+    for file in options.credential_files {
+        let path = match *params::AUTH_FILE_MATCHING {
+            AuthFileMatching::Path => String::from_utf8_lossy(&file.path).into_owned(),
+            AuthFileMatching::Inode => file.absolute_path.to_string_lossy().into_owned(),
+        };
+        match file.kind {
+            CredentialKind::AuthUserPass => {
+                discovered.auth_user_pass = Some((AuthFileKind::UserPass, path))
+            }
+            CredentialKind::InlineAuthUserPass => {
+                discovered.auth_user_pass = Some((AuthFileKind::Config, path))
+            }
+            CredentialKind::Askpass => discovered.askpass = Some(path),
+            CredentialKind::HttpProxy => discovered.proxy = Some((AuthFileKind::HttpProxy, path)),
+            CredentialKind::SocksProxy => discovered.proxy = Some((AuthFileKind::SocksProxy, path)),
+        }
+    }
+    Some(discovered)
+}
+
+/// Whether the current process is OpenVPN, from the name of its executable or
+/// the name it was run as (`program`, its first argument).
+fn is_openvpn(program: &[u8]) -> bool {
+    let executable = fs::read_link("/proc/self/exe").unwrap_or_default();
+    [Path::new(OsStr::from_bytes(program)), &executable]
+        .iter()
+        .any(|path| path.file_name() == Some(OsStr::new(OPENVPN)))
+}
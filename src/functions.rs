@@ -9,50 +9,349 @@
 //! Each original libc function is exposed as Rust functions under the
 //! `Functions` struct namespace, which is initialized lazily. They can be
 //! invoked as `Functions::fopen` and so on.
+//!
+//! `dlsym` only finds the default version of each symbol. Functions that glibc
+//! only keeps for compatibility (e.g., `_IO_getc` in recent versions) are
+//! looked up with `dlvsym` and an explicit glibc version instead. If a function
+//! can't be found at all, calling it reports the error and fails with
+//! `ENOSYS`, as any call to it would be a bug anyway (the program that called
+//! our replacement must have been linked against it).
 
-use libc::{c_char, c_int, c_void, dlsym, FILE, RTLD_NEXT};
+use libc::{
+    c_char, c_int, c_long, c_uint, c_void, dlsym, fpos64_t, fpos_t, mode_t, off64_t, off_t, size_t,
+    ssize_t, stat, stat64, statx, FILE, RTLD_NEXT,
+};
 use once_cell::sync::Lazy;
-use std::ffi::CString;
-use std::mem;
+use std::ffi::{CStr, CString};
+use std::{mem, ptr};
 
 static ORIGINAL_FUNCTIONS: Lazy<Functions> = Lazy::new(|| unsafe { Functions::new() });
 
-type FOpenFn = extern "C" fn(filename: *const c_char, mode: *const c_char) -> *mut FILE;
-type FGetsFn = extern "C" fn(buf: *mut c_char, n: c_int, stream: *mut FILE) -> *mut c_char;
-type FCloseFn = extern "C" fn(stream: *mut FILE) -> c_int;
+/// glibc versions to look up the original functions with, when `dlsym` can't
+/// find them. The base version of the architecture comes first, as that's the
+/// version of most of them.
+#[cfg(target_arch = "x86_64")]
+const GLIBC_VERSIONS: [&CStr; 4] = [c"GLIBC_2.2.5", c"GLIBC_2.3.4", c"GLIBC_2.4", c"GLIBC_2.7"];
+#[cfg(target_arch = "aarch64")]
+const GLIBC_VERSIONS: [&CStr; 1] = [c"GLIBC_2.17"];
+
+// Only these architectures have their glibc versions listed above, and the
+// naked `fscanf` trampolines (see `readers.rs`) written for them
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+compile_error!("unsupported architecture: only x86_64 and aarch64 are supported");
 
-/// Namespace for invoking the original libc functions.
-pub struct Functions {
-    fopen: FOpenFn,
-    fgets: FGetsFn,
-    fclose: FCloseFn,
+extern "C" {
+    /// glibc's `dlvsym`, which is not exposed by the `libc` crate.
+    fn dlvsym(handle: *mut c_void, symbol: *const c_char, version: *const c_char) -> *mut c_void;
 }
 
-impl Functions {
-    unsafe fn new() -> Self {
-        Functions {
-            fopen: mem::transmute(Self::original_fn("fopen")),
-            fgets: mem::transmute(Self::original_fn("fgets")),
-            fclose: mem::transmute(Self::original_fn("fclose")),
+/// A C `va_list`, as received by the `v*` family of functions (e.g.,
+/// `vfscanf`).
+///
+/// Both in x86-64 and AArch64 (Linux) a `va_list` function argument is passed
+/// as a pointer, so we just treat it as an opaque pointer that we forward to
+/// the original functions.
+pub type VaList = *mut c_void;
+
+/// Declares the original functions that we need access to.
+///
+/// For each `name = "symbol": fn(args) -> ret` entry, it adds a `name` field
+/// to `Functions`, holding the address of `symbol` (if found), and a
+/// `Functions::name` function to invoke it.
+macro_rules! original_functions {
+    ($($name:ident = $symbol:literal: fn($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty;)*) => {
+        /// Namespace for invoking the original libc functions.
+        pub struct Functions {
+            $($name: Option<extern "C" fn($($ty),*) -> $ret>,)*
         }
-    }
 
+        impl Functions {
+            unsafe fn new() -> Self {
+                Functions {
+                    // A null pointer becomes `None`
+                    $($name: mem::transmute::<
+                        *mut c_void,
+                        Option<extern "C" fn($($ty),*) -> $ret>,
+                    >(Self::original_fn($symbol)),)*
+                }
+            }
+
+            $(
+                pub fn $name($($arg: $ty),*) -> $ret {
+                    match ORIGINAL_FUNCTIONS.$name {
+                        Some(original) => original($($arg),*),
+                        None => unresolved($symbol),
+                    }
+                }
+            )*
+        }
+    };
+}
+
+original_functions! {
+    fopen = "fopen": fn(filename: *const c_char, mode: *const c_char) -> *mut FILE;
+    fopen64 = "fopen64": fn(filename: *const c_char, mode: *const c_char) -> *mut FILE;
+    freopen = "freopen": fn(
+        filename: *const c_char,
+        mode: *const c_char,
+        stream: *mut FILE,
+    ) -> *mut FILE;
+    freopen64 = "freopen64": fn(
+        filename: *const c_char,
+        mode: *const c_char,
+        stream: *mut FILE,
+    ) -> *mut FILE;
+    fdopen = "fdopen": fn(fd: c_int, mode: *const c_char) -> *mut FILE;
+    fgets = "fgets": fn(buf: *mut c_char, n: c_int, stream: *mut FILE) -> *mut c_char;
+    fgets_chk = "__fgets_chk": fn(
+        buf: *mut c_char,
+        size: size_t,
+        n: c_int,
+        stream: *mut FILE,
+    ) -> *mut c_char;
+    fgets_unlocked = "fgets_unlocked": fn(
+        buf: *mut c_char,
+        n: c_int,
+        stream: *mut FILE,
+    ) -> *mut c_char;
+    fgets_unlocked_chk = "__fgets_unlocked_chk": fn(
+        buf: *mut c_char,
+        size: size_t,
+        n: c_int,
+        stream: *mut FILE,
+    ) -> *mut c_char;
+    getline = "getline": fn(
+        lineptr: *mut *mut c_char,
+        n: *mut size_t,
+        stream: *mut FILE,
+    ) -> ssize_t;
+    getdelim = "getdelim": fn(
+        lineptr: *mut *mut c_char,
+        n: *mut size_t,
+        delim: c_int,
+        stream: *mut FILE,
+    ) -> ssize_t;
+    getdelim_internal = "__getdelim": fn(
+        lineptr: *mut *mut c_char,
+        n: *mut size_t,
+        delim: c_int,
+        stream: *mut FILE,
+    ) -> ssize_t;
+    fgetc = "fgetc": fn(stream: *mut FILE) -> c_int;
+    getc = "getc": fn(stream: *mut FILE) -> c_int;
+    io_getc = "_IO_getc": fn(stream: *mut FILE) -> c_int;
+    fgetc_unlocked = "fgetc_unlocked": fn(stream: *mut FILE) -> c_int;
+    getc_unlocked = "getc_unlocked": fn(stream: *mut FILE) -> c_int;
+    uflow = "__uflow": fn(stream: *mut FILE) -> c_int;
+    underflow = "__underflow": fn(stream: *mut FILE) -> c_int;
+    fread = "fread": fn(
+        ptr: *mut c_void,
+        size: size_t,
+        nmemb: size_t,
+        stream: *mut FILE,
+    ) -> size_t;
+    fread_unlocked = "fread_unlocked": fn(
+        ptr: *mut c_void,
+        size: size_t,
+        nmemb: size_t,
+        stream: *mut FILE,
+    ) -> size_t;
+    fread_chk = "__fread_chk": fn(
+        ptr: *mut c_void,
+        ptrlen: size_t,
+        size: size_t,
+        nmemb: size_t,
+        stream: *mut FILE,
+    ) -> size_t;
+    fread_unlocked_chk = "__fread_unlocked_chk": fn(
+        ptr: *mut c_void,
+        ptrlen: size_t,
+        size: size_t,
+        nmemb: size_t,
+        stream: *mut FILE,
+    ) -> size_t;
+    vfscanf = "vfscanf": fn(stream: *mut FILE, format: *const c_char, ap: VaList) -> c_int;
+    isoc99_vfscanf = "__isoc99_vfscanf": fn(
+        stream: *mut FILE,
+        format: *const c_char,
+        ap: VaList,
+    ) -> c_int;
+    isoc23_vfscanf = "__isoc23_vfscanf": fn(
+        stream: *mut FILE,
+        format: *const c_char,
+        ap: VaList,
+    ) -> c_int;
+    ungetc = "ungetc": fn(c: c_int, stream: *mut FILE) -> c_int;
+    rewind = "rewind": fn(stream: *mut FILE) -> ();
+    fseek = "fseek": fn(stream: *mut FILE, offset: c_long, whence: c_int) -> c_int;
+    fseeko = "fseeko": fn(stream: *mut FILE, offset: off_t, whence: c_int) -> c_int;
+    fseeko64 = "fseeko64": fn(stream: *mut FILE, offset: off64_t, whence: c_int) -> c_int;
+    fsetpos = "fsetpos": fn(stream: *mut FILE, pos: *const fpos_t) -> c_int;
+    fsetpos64 = "fsetpos64": fn(stream: *mut FILE, pos: *const fpos64_t) -> c_int;
+    ftell = "ftell": fn(stream: *mut FILE) -> c_long;
+    ftello = "ftello": fn(stream: *mut FILE) -> off_t;
+    ftello64 = "ftello64": fn(stream: *mut FILE) -> off64_t;
+    fgetpos = "fgetpos": fn(stream: *mut FILE, pos: *mut fpos_t) -> c_int;
+    fgetpos64 = "fgetpos64": fn(stream: *mut FILE, pos: *mut fpos64_t) -> c_int;
+    fclose = "fclose": fn(stream: *mut FILE) -> c_int;
+    fcloseall = "fcloseall": fn() -> c_int;
+    open = "open": fn(path: *const c_char, flags: c_int, mode: mode_t) -> c_int;
+    open64 = "open64": fn(path: *const c_char, flags: c_int, mode: mode_t) -> c_int;
+    open_2 = "__open_2": fn(path: *const c_char, flags: c_int) -> c_int;
+    open64_2 = "__open64_2": fn(path: *const c_char, flags: c_int) -> c_int;
+    openat = "openat": fn(dirfd: c_int, path: *const c_char, flags: c_int, mode: mode_t) -> c_int;
+    openat64 = "openat64": fn(
+        dirfd: c_int,
+        path: *const c_char,
+        flags: c_int,
+        mode: mode_t,
+    ) -> c_int;
+    openat_2 = "__openat_2": fn(dirfd: c_int, path: *const c_char, flags: c_int) -> c_int;
+    openat64_2 = "__openat64_2": fn(dirfd: c_int, path: *const c_char, flags: c_int) -> c_int;
+    read = "read": fn(fd: c_int, buf: *mut c_void, count: size_t) -> ssize_t;
+    read_chk = "__read_chk": fn(
+        fd: c_int,
+        buf: *mut c_void,
+        count: size_t,
+        buflen: size_t,
+    ) -> ssize_t;
+    pread = "pread": fn(fd: c_int, buf: *mut c_void, count: size_t, offset: off_t) -> ssize_t;
+    pread64 = "pread64": fn(
+        fd: c_int,
+        buf: *mut c_void,
+        count: size_t,
+        offset: off64_t,
+    ) -> ssize_t;
+    pread_chk = "__pread_chk": fn(
+        fd: c_int,
+        buf: *mut c_void,
+        count: size_t,
+        offset: off_t,
+        buflen: size_t,
+    ) -> ssize_t;
+    pread64_chk = "__pread64_chk": fn(
+        fd: c_int,
+        buf: *mut c_void,
+        count: size_t,
+        offset: off64_t,
+        buflen: size_t,
+    ) -> ssize_t;
+    lseek = "lseek": fn(fd: c_int, offset: off_t, whence: c_int) -> off_t;
+    lseek64 = "lseek64": fn(fd: c_int, offset: off64_t, whence: c_int) -> off64_t;
+    close = "close": fn(fd: c_int) -> c_int;
+    stat = "stat": fn(path: *const c_char, buf: *mut stat) -> c_int;
+    stat64 = "stat64": fn(path: *const c_char, buf: *mut stat64) -> c_int;
+    lstat = "lstat": fn(path: *const c_char, buf: *mut stat) -> c_int;
+    lstat64 = "lstat64": fn(path: *const c_char, buf: *mut stat64) -> c_int;
+    fstatat = "fstatat": fn(
+        dirfd: c_int,
+        path: *const c_char,
+        buf: *mut stat,
+        flags: c_int,
+    ) -> c_int;
+    fstatat64 = "fstatat64": fn(
+        dirfd: c_int,
+        path: *const c_char,
+        buf: *mut stat64,
+        flags: c_int,
+    ) -> c_int;
+    xstat = "__xstat": fn(ver: c_int, path: *const c_char, buf: *mut stat) -> c_int;
+    xstat64 = "__xstat64": fn(ver: c_int, path: *const c_char, buf: *mut stat64) -> c_int;
+    lxstat = "__lxstat": fn(ver: c_int, path: *const c_char, buf: *mut stat) -> c_int;
+    lxstat64 = "__lxstat64": fn(ver: c_int, path: *const c_char, buf: *mut stat64) -> c_int;
+    fxstatat = "__fxstatat": fn(
+        ver: c_int,
+        dirfd: c_int,
+        path: *const c_char,
+        buf: *mut stat,
+        flags: c_int,
+    ) -> c_int;
+    fxstatat64 = "__fxstatat64": fn(
+        ver: c_int,
+        dirfd: c_int,
+        path: *const c_char,
+        buf: *mut stat64,
+        flags: c_int,
+    ) -> c_int;
+    statx = "statx": fn(
+        dirfd: c_int,
+        path: *const c_char,
+        flags: c_int,
+        mask: c_uint,
+        buf: *mut statx,
+    ) -> c_int;
+    access = "access": fn(path: *const c_char, mode: c_int) -> c_int;
+    faccessat = "faccessat": fn(dirfd: c_int, path: *const c_char, mode: c_int, flags: c_int) -> c_int;
+    euidaccess = "euidaccess": fn(path: *const c_char, mode: c_int) -> c_int;
+    eaccess = "eaccess": fn(path: *const c_char, mode: c_int) -> c_int;
+}
+
+impl Functions {
+    /// Address of the original function `fn_name`, or null if it can't be
+    /// found in any of the known glibc versions.
     fn original_fn(fn_name: &str) -> *mut c_void {
         let open_name = CString::new(fn_name.as_bytes())
             // Safe to unwrap, as we know the string doesn't have any null bytes
             .unwrap();
-        unsafe { dlsym(RTLD_NEXT, open_name.as_ptr()) }
+        let address = unsafe { dlsym(RTLD_NEXT, open_name.as_ptr()) };
+        if !address.is_null() {
+            return address;
+        }
+        GLIBC_VERSIONS
+            .iter()
+            .map(|version| unsafe { dlvsym(RTLD_NEXT, open_name.as_ptr(), version.as_ptr()) })
+            .find(|address| !address.is_null())
+            .unwrap_or(ptr::null_mut())
+    }
+}
+
+/// Report that the original function `symbol` could not be found, and fail
+/// the call to it with `ENOSYS`.
+#[cold]
+fn unresolved<R: ErrorValue>(symbol: &str) -> R {
+    error!("Unable to find the original {symbol} function in libc. Failing the call");
+    unsafe { *libc::__errno_location() = libc::ENOSYS };
+    R::error_value()
+}
+
+/// Value returned by the original functions to signal an error.
+trait ErrorValue {
+    fn error_value() -> Self;
+}
+
+/// `-1` or `EOF`.
+impl ErrorValue for c_int {
+    fn error_value() -> Self {
+        -1
+    }
+}
+
+/// Also `off_t` and `off64_t`.
+impl ErrorValue for c_long {
+    fn error_value() -> Self {
+        -1
     }
+}
 
-    pub fn fopen(filename: *const c_char, mode: *const c_char) -> *mut FILE {
-        (ORIGINAL_FUNCTIONS.fopen)(filename, mode)
+impl ErrorValue for ssize_t {
+    fn error_value() -> Self {
+        -1
     }
+}
 
-    pub fn fgets(buf: *mut c_char, n: c_int, stream: *mut FILE) -> *mut c_char {
-        (ORIGINAL_FUNCTIONS.fgets)(buf, n, stream)
+/// Number of items read by `fread`.
+impl ErrorValue for size_t {
+    fn error_value() -> Self {
+        0
     }
+}
 
-    pub fn fclose(stream: *mut FILE) -> c_int {
-        (ORIGINAL_FUNCTIONS.fclose)(stream)
+impl<T> ErrorValue for *mut T {
+    fn error_value() -> Self {
+        ptr::null_mut()
     }
 }
+
+impl ErrorValue for () {
+    fn error_value() -> Self {}
+}
@@ -0,0 +1,123 @@
+//! Panic safety for the hooks.
+//!
+//! All our hooks are called from C code, so a panic must never unwind out of
+//! them (that would be undefined behavior). Every hook runs its logic through
+//! `catch_panic` (or `or_original`), which catches any panic, reports it and
+//! lets the hook fall back to the behavior of the original libc function.
+//!
+//! It also makes the hooks non-reentrant: any libc call made by a hook while
+//! it is running (e.g., to log, to read the machine-id, or by the panic hook)
+//! may end up calling one of our hooks again. Such nested calls always go
+//! straight to the original functions, as the hook logic is not meant to run
+//! twice at the same time in the same thread (and it would deadlock if the
+//! outer call held the global state lock).
+//!
+//! In debug builds, panics can be injected into any hook to test this, by
+//! setting the `AUTH_HOOK_INJECT_PANIC` environment variable to a comma
+//! separated list of hook names (e.g., `fgets,fread`). The special name
+//! `state` injects the panic while the global state lock is held instead.
+
+use std::{
+    any::Any,
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::state::State;
+
+thread_local! {
+    /// Whether this thread is running the logic of a hook.
+    static IN_HOOK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run `f`, the logic of the hook named `name`. Returns `None` if it panicked,
+/// in which case the panic has already been reported, or if this is a nested
+/// call from another hook, in which case `f` is not run at all.
+pub fn catch_panic<R>(name: &str, f: impl FnOnce() -> R) -> Option<R> {
+    let _scope = HookScope::enter()?;
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        inject_panic(name);
+        f()
+    }));
+    match result {
+        Ok(value) => Some(value),
+        Err(payload) => {
+            error!(
+                "Unexpected panic in {name}, falling back to the original function: {}",
+                panic_message(&*payload)
+            );
+            None
+        }
+    }
+}
+
+/// Run `f`, the logic of the hook named `name` for tracked streams and file
+/// descriptors, or `original` (the original function) if `f` returns `None`
+/// (i.e., if the hook doesn't apply), panics, or is not run because this is a
+/// nested call. When nothing is tracked, `original` is called right away.
+pub fn or_original<R>(
+    name: &str,
+    f: impl FnOnce() -> Option<R>,
+    original: impl FnOnce() -> R,
+) -> R {
+    if !State::is_tracking() {
+        // Fast path for every other file in the process
+        return original();
+    }
+    catch_panic(name, f).flatten().unwrap_or_else(original)
+}
+
+/// Marks this thread as running the logic of a hook, until dropped (even if
+/// the hook panics).
+struct HookScope;
+
+impl HookScope {
+    /// Enter a hook. Returns `None` if this thread is already in one.
+    fn enter() -> Option<Self> {
+        let nested = IN_HOOK.with(|in_hook| in_hook.replace(true));
+        // Not `then_some`, as that would create (and drop) the scope anyway
+        if nested {
+            None
+        } else {
+            Some(HookScope)
+        }
+    }
+}
+
+impl Drop for HookScope {
+    fn drop(&mut self) {
+        IN_HOOK.with(|in_hook| in_hook.set(false));
+    }
+}
+
+/// Panic if a panic has been requested for `point` with `AUTH_HOOK_INJECT_PANIC`.
+/// Only in debug builds.
+pub fn inject_panic(point: &str) {
+    #[cfg(debug_assertions)]
+    {
+        use once_cell::sync::Lazy;
+        use std::env;
+
+        static POINTS: Lazy<Vec<String>> = Lazy::new(|| {
+            env::var("AUTH_HOOK_INJECT_PANIC")
+                .map(|points| points.split(',').map(str::to_owned).collect())
+                .unwrap_or_default()
+        });
+        if POINTS.iter().any(|injected| injected == point) {
+            panic!("Injected panic in {point}");
+        }
+    }
+    #[cfg(not(debug_assertions))]
+    let _ = point;
+}
+
+/// Message of a panic, from its payload.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
@@ -1,16 +1,28 @@
 #![doc = include_str!("../README.md")]
 
+#[macro_use]
+mod log;
+
+mod config;
+mod content;
+mod descriptors;
+mod discovery;
 mod functions;
+mod guard;
+mod matching;
+mod memory_files;
+mod metadata;
 mod params;
+mod positioning;
+mod readers;
+mod settings;
 mod state;
 
-use std::{
-    ffi::{CStr, CString},
-    ptr,
-};
+use std::{ffi::CStr, ptr};
 
 use functions::Functions;
-use libc::{c_char, c_int, FILE};
+use libc::{c_char, c_int, size_t, AT_FDCWD, FILE};
+use params::{AuthFile, MissingPasswordPolicy, ReadWritePolicy};
 use state::State;
 
 /// The contents of the `auth-user-pass` file used by OpenVPN must be the
@@ -18,6 +30,29 @@ use state::State;
 /// username and the second line must be the password.
 const PASSWORD_LINE_NUMBER: usize = 2;
 
+/// Line of the `auth-user-pass` file with the username, which is only replaced
+/// if a username was embedded along with the password (see `build.rs`).
+const USERNAME_LINE_NUMBER: usize = 1;
+
+/// The `askpass` file used by OpenVPN has the private key passphrase in its
+/// first (and only) line.
+const PASSPHRASE_LINE_NUMBER: usize = 1;
+
+/// Library constructor, run by the dynamic loader when the hook is loaded,
+/// before the `main` function of OpenVPN.
+#[used]
+#[link_section = ".init_array"]
+static CONSTRUCTOR: extern "C" fn() = constructor;
+
+/// See `CONSTRUCTOR`. It runs as a hook, so that the files it reads are read
+/// with the original functions.
+extern "C" fn constructor() {
+    guard::catch_panic("constructor", || {
+        discovery::init();
+        matching::init();
+    });
+}
+
 /// Replacement for the `fopen` libc function.
 ///
 /// If the file being opened is the `auth-user-pass` file, it tracks the
@@ -30,20 +65,119 @@ const PASSWORD_LINE_NUMBER: usize = 2;
 ///
 #[no_mangle]
 pub unsafe extern "C" fn fopen(filename: *const c_char, mode: *const c_char) -> *mut FILE {
-    let stream = Functions::fopen(filename, mode);
-    if !stream.is_null() {
-        if let Some(auth_file_path) = params::AUTH_FILE_PATH.as_ref() {
-            let args = {
-                (
-                    CStr::from_ptr(filename).to_str(),
-                    CStr::from_ptr(mode).to_str(),
-                )
-            };
-            if args == (Ok(auth_file_path), Ok("r")) {
-                State::add(stream);
-            }
+    let stream = memory_files::with_path("fopen", AT_FDCWD, filename, |filename| {
+        Functions::fopen(filename, mode)
+    });
+    guard::catch_panic("fopen", || {
+        if let Some(auth_file) = tracked_auth_file(filename, mode, stream) {
+            State::add(stream, auth_file);
         }
-    }
+    });
+    stream
+}
+
+/// Replacement for the `fopen64` libc function.
+///
+/// Programs built with `-D_FILE_OFFSET_BITS=64` call it instead of `fopen`. It
+/// behaves exactly like our `fopen` replacement.
+///
+/// # Safety
+///
+/// `filename` and `mode` must be valid C strings.
+#[no_mangle]
+pub unsafe extern "C" fn fopen64(filename: *const c_char, mode: *const c_char) -> *mut FILE {
+    let stream = memory_files::with_path("fopen64", AT_FDCWD, filename, |filename| {
+        Functions::fopen64(filename, mode)
+    });
+    guard::catch_panic("fopen64", || {
+        if let Some(auth_file) = tracked_auth_file(filename, mode, stream) {
+            State::add(stream, auth_file);
+        }
+    });
+    stream
+}
+
+/// Replacement for the `freopen` libc function.
+///
+/// The original file associated to `stream` is closed, so `stream` stops being
+/// tracked. If the newly opened file is the `auth-user-pass` file, `stream` is
+/// tracked again from the beginning of the file.
+///
+/// When `filename` is null, `freopen` just changes the mode of the file that
+/// is already associated to `stream`. In that case `stream` is tracked again
+/// only if it was tracked before and the new mode still allows reading it.
+///
+/// # Safety
+///
+/// `filename` must be null or a valid C string. `mode` must be a valid C
+/// string. `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn freopen(
+    filename: *const c_char,
+    mode: *const c_char,
+    stream: *mut FILE,
+) -> *mut FILE {
+    let previous_auth_file = guard::catch_panic("freopen", || State::remove(stream)).flatten();
+    let new_stream = memory_files::with_path("freopen", AT_FDCWD, filename, |filename| {
+        Functions::freopen(filename, mode, stream)
+    });
+    guard::catch_panic("freopen", || {
+        if let Some(auth_file) = reopened_auth_file(filename, mode, new_stream, previous_auth_file)
+        {
+            State::add(new_stream, auth_file);
+        }
+    });
+    new_stream
+}
+
+/// Replacement for the `freopen64` libc function.
+///
+/// The large file variant of `freopen`. It behaves exactly like our `freopen`
+/// replacement.
+///
+/// # Safety
+///
+/// `filename` must be null or a valid C string. `mode` must be a valid C
+/// string. `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn freopen64(
+    filename: *const c_char,
+    mode: *const c_char,
+    stream: *mut FILE,
+) -> *mut FILE {
+    let previous_auth_file = guard::catch_panic("freopen64", || State::remove(stream)).flatten();
+    let new_stream = memory_files::with_path("freopen64", AT_FDCWD, filename, |filename| {
+        Functions::freopen64(filename, mode, stream)
+    });
+    guard::catch_panic("freopen64", || {
+        if let Some(auth_file) = reopened_auth_file(filename, mode, new_stream, previous_auth_file)
+        {
+            State::add(new_stream, auth_file);
+        }
+    });
+    new_stream
+}
+
+/// Replacement for the `fdopen` libc function.
+///
+/// There is no path to compare against in this case, so the stream is tracked
+/// if the file descriptor refers to the same file (same device and inode) as
+/// the `auth-user-pass` file.
+///
+/// # Safety
+///
+/// `mode` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn fdopen(fd: c_int, mode: *const c_char) -> *mut FILE {
+    let stream = Functions::fdopen(fd, mode);
+    guard::catch_panic("fdopen", || {
+        if stream.is_null() || !is_tracked_mode(mode) {
+            return;
+        }
+        if let Some(auth_file) = matching::auth_file_descriptor(fd) {
+            State::add(stream, auth_file);
+        }
+    });
     stream
 }
 
@@ -58,49 +192,149 @@ pub unsafe extern "C" fn fopen(filename: *const c_char, mode: *const c_char) ->
 /// created by `fopen` and not yet closed.
 #[no_mangle]
 pub unsafe extern "C" fn fgets(buf: *mut c_char, n: c_int, stream: *mut FILE) -> *mut c_char {
-    unsafe fn replace_line(buf: *mut c_char, n: c_int, new_line: &CString) {
-        let new_line_len = new_line.as_bytes_with_nul().len();
-        let available_space = n.try_into().unwrap_or(0);
-        if new_line_len <= available_space {
-            ptr::copy_nonoverlapping(new_line.as_ptr(), buf, new_line_len);
-        } else {
-            eprintln!(
-                "[Hook] WARNING: Replacement line is too long to fit in the buffer \
-                 ({new_line_len} > {available_space})"
-            );
+    read_line("fgets", buf, n.try_into().unwrap_or(0), stream, || {
+        Functions::fgets(buf, n, stream)
+    })
+}
+
+/// Replacement for the `__fgets_chk` glibc function.
+///
+/// This is the variant of `fgets` that programs compiled with
+/// `-D_FORTIFY_SOURCE` call instead of `fgets` (as is the case for most distro
+/// builds of OpenVPN). It takes an additional `size` argument with the size of
+/// the destination buffer as known by the compiler. It behaves exactly like our
+/// `fgets` replacement, but the replacement line must also fit in `size`.
+///
+/// # Safety
+///
+/// `buf` must be a valid C string of at least `size` bytes. `stream` must be a
+/// valid pointer to a FILE, created by `fopen` and not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn __fgets_chk(
+    buf: *mut c_char,
+    size: size_t,
+    n: c_int,
+    stream: *mut FILE,
+) -> *mut c_char {
+    let available_space = n.try_into().unwrap_or(0);
+    if available_space > size {
+        // Let the original function report the buffer overflow
+        return Functions::fgets_chk(buf, size, n, stream);
+    }
+    read_line("__fgets_chk", buf, available_space, stream, || {
+        Functions::fgets_chk(buf, size, n, stream)
+    })
+}
+
+/// Replacement for the `fgets_unlocked` glibc function.
+///
+/// Same as `fgets`, but without locking the stream.
+///
+/// # Safety
+///
+/// `buf` must be a valid C string. `stream` must be a valid pointer to a FILE,
+/// created by `fopen` and not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn fgets_unlocked(
+    buf: *mut c_char,
+    n: c_int,
+    stream: *mut FILE,
+) -> *mut c_char {
+    read_line(
+        "fgets_unlocked",
+        buf,
+        n.try_into().unwrap_or(0),
+        stream,
+        || Functions::fgets_unlocked(buf, n, stream),
+    )
+}
+
+/// Replacement for the `__fgets_unlocked_chk` glibc function.
+///
+/// The `-D_FORTIFY_SOURCE` variant of `fgets_unlocked`. See `__fgets_chk`.
+///
+/// # Safety
+///
+/// `buf` must be a valid C string of at least `size` bytes. `stream` must be a
+/// valid pointer to a FILE, created by `fopen` and not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn __fgets_unlocked_chk(
+    buf: *mut c_char,
+    size: size_t,
+    n: c_int,
+    stream: *mut FILE,
+) -> *mut c_char {
+    let available_space = n.try_into().unwrap_or(0);
+    if available_space > size {
+        // Let the original function report the buffer overflow
+        return Functions::fgets_unlocked_chk(buf, size, n, stream);
+    }
+    read_line("__fgets_unlocked_chk", buf, available_space, stream, || {
+        Functions::fgets_unlocked_chk(buf, size, n, stream)
+    })
+}
+
+/// Common logic for the `fgets` family of replacements.
+///
+/// Lines before the first replaced line (the password line, or the username
+/// line if the secret has a username) are read with the `original` function,
+/// and the stream position is tracked in bytes and lines as they are read, so
+/// it doesn't matter whether `buf` fits whole lines or just chunks of them.
+/// Once the next read would start the first replaced line (or if the virtual
+/// content of the stream was already loaded by a different reader function),
+/// the rest of the stream is served from its virtual content, where the lines
+/// have already been replaced. That way the replacement line is handed out in as
+/// many chunks as `buf` requires. `available_space` is the size of `buf`.
+unsafe fn read_line(
+    name: &str,
+    buf: *mut c_char,
+    available_space: usize,
+    stream: *mut FILE,
+    original: impl FnOnce() -> *mut c_char,
+) -> *mut c_char {
+    if !State::is_tracking() {
+        // Fast path for every other file in the process
+        return original();
+    }
+    let virtual_read = guard::catch_panic(name, || {
+        if !State::reached_replaced_line(stream) {
+            return None;
+        }
+        let (read, hit_eof) =
+            State::with_content(stream, |content| content.read_line(buf, available_space))?;
+        if hit_eof {
+            content::set_eof_indicator(stream);
         }
+        Some(if read { buf } else { ptr::null_mut() })
+    });
+    if let Some(Some(response_buffer)) = virtual_read {
+        return response_buffer;
     }
 
-    let response_buffer = Functions::fgets(buf, n, stream);
+    let response_buffer = original();
     if !response_buffer.is_null() {
-        // NOTE: The implementation here is quite simplistic, but good enough in
-        // practice.
-        //
-        // Considering `fget`'s behavior (quoted excerpt from `man 3 fgets`):
-        // > `fgets()` reads in at most one less than `size` characters from `stream`
-        //   and stores them into the buffer pointed to by `s`. Reading stops after an
-        //   EOF or a newline. If a newline is read, it is stored into the buffer. A
-        //   terminating null byte ('\0') is stored after the last character in the
-        //   buffer.
-        //
-        // Our assumption is that one call to `fgets` is equivalent to reading
-        // one line of text. This is not necessarily true, as for lines longer
-        // than the buffer size ([4096 as of OpenVPN
-        // 2.6.5][openvpn-buffer-size]) `fgets` will only produce fractions of a
-        // line. But it seems unlikely that this would be the case for the
-        // `auth-user-pass` file, and OpenVPN itself also
-        // [assumes][openvpn-auth-file-read] that username/password lines will
-        // fit in the buffer.
-        //
-        // [openvpn-buffer-size]: https://github.com/OpenVPN/openvpn/blob/v2.6.5/src/openvpn/misc.h#L64-L73
-        // [openvpn-auth-file-read]: https://github.com/OpenVPN/openvpn/blob/v2.6.5/src/openvpn/misc.c#L211-L252
-        if State::inc_lines(stream) == Some(PASSWORD_LINE_NUMBER) {
-            match params::password_line() {
-                Ok(password_line) => replace_line(buf, n, &password_line),
-                Err(err) => {
-                    eprintln!("[Hook] ERROR: Unexpected error obtaining the password: {err}")
-                }
+        // NOTE: Lines with embedded null bytes would be miscounted here, but
+        // `fgets` callers can't tell where such lines end anyway.
+        guard::catch_panic(name, || {
+            State::advance(stream, CStr::from_ptr(buf).to_bytes())
+        });
+    } else if libc::feof(stream) != 0 {
+        // The file may have ended in the middle of the username line (i.e.,
+        // without a trailing newline), before the password line could be
+        // reached. Its virtual content then has the password line only
+        let virtual_read = guard::catch_panic(name, || {
+            if *params::AUTH_FILE_MISSING_PASSWORD != MissingPasswordPolicy::Append {
+                return None;
+            }
+            let (read, hit_eof) =
+                State::with_content(stream, |content| content.read_line(buf, available_space))?;
+            if hit_eof {
+                content::set_eof_indicator(stream);
             }
+            read.then_some(buf)
+        });
+        if let Some(Some(response_buffer)) = virtual_read {
+            return response_buffer;
         }
     }
     response_buffer
@@ -117,6 +351,94 @@ pub unsafe extern "C" fn fgets(buf: *mut c_char, n: c_int, stream: *mut FILE) ->
 /// closed.
 #[no_mangle]
 pub unsafe extern "C" fn fclose(stream: *mut FILE) -> c_int {
-    State::remove(stream);
+    guard::catch_panic("fclose", || State::remove(stream));
     Functions::fclose(stream)
 }
+
+/// Replacement for the `fcloseall` glibc function.
+///
+/// glibc closes the streams without going through `fclose`, so all of them are
+/// removed from the list of tracked pointers here.
+#[no_mangle]
+pub extern "C" fn fcloseall() -> c_int {
+    guard::catch_panic("fcloseall", State::remove_all);
+    Functions::fcloseall()
+}
+
+/// The auth file that `filename` (which has been opened as `stream`) is, if
+/// any and `mode` allows reading it (see `is_tracked_mode`). `stream` is null
+/// if the file couldn't be opened.
+unsafe fn tracked_auth_file(
+    filename: *const c_char,
+    mode: *const c_char,
+    stream: *mut FILE,
+) -> Option<&'static AuthFile> {
+    if stream.is_null() || !is_tracked_mode(mode) {
+        return None;
+    }
+    matching::auth_file(filename, libc::fileno(stream))
+}
+
+/// The auth file that the stream resulting from a `freopen` call is, if it
+/// must be tracked. `previous_auth_file` is the one that the stream was before
+/// the call. See `freopen` for details.
+unsafe fn reopened_auth_file(
+    filename: *const c_char,
+    mode: *const c_char,
+    stream: *mut FILE,
+    previous_auth_file: Option<&'static AuthFile>,
+) -> Option<&'static AuthFile> {
+    if filename.is_null() {
+        previous_auth_file.filter(|_| !stream.is_null() && is_tracked_mode(mode))
+    } else {
+        tracked_auth_file(filename, mode, stream)
+    }
+}
+
+/// Whether a stream opened with `mode` (an `fopen` mode string) must be
+/// tracked. See `is_tracked_access`.
+unsafe fn is_tracked_mode(mode: *const c_char) -> bool {
+    mode_access(CStr::from_ptr(mode).to_bytes()).is_some_and(is_tracked_access)
+}
+
+/// Whether a file opened with `access` must be tracked: read-only files always
+/// are, read-write files only if `AUTH_FILE_READ_WRITE` says so, and files that
+/// can't be read never are.
+pub(crate) fn is_tracked_access(access: Access) -> bool {
+    match access {
+        Access::ReadOnly => true,
+        Access::ReadWrite => *params::AUTH_FILE_READ_WRITE == ReadWritePolicy::Track,
+        Access::WriteOnly => false,
+    }
+}
+
+/// Access granted to a stream opened with `mode` (an `fopen` mode string), or
+/// `None` if `mode` is invalid.
+///
+/// The mode is parsed the way glibc does: the first character is `r`, `w` or
+/// `a`, and it may be followed by up to six modifiers, of which only `+`
+/// (update, i.e., read-write) matters here. Unknown modifiers (e.g., `b`, `e`
+/// for `O_CLOEXEC`, `m` for `mmap`, `x` for `O_EXCL`) are ignored, and parsing
+/// stops at the `,ccs=` suffix.
+fn mode_access(mode: &[u8]) -> Option<Access> {
+    let (&first, modifiers) = mode.split_first()?;
+    let update = modifiers
+        .iter()
+        .take(6)
+        .take_while(|&&modifier| modifier != b',')
+        .any(|&modifier| modifier == b'+');
+    match (first, update) {
+        (b'r', false) => Some(Access::ReadOnly),
+        (b'r' | b'w' | b'a', true) => Some(Access::ReadWrite),
+        (b'w' | b'a', false) => Some(Access::WriteOnly),
+        _ => None,
+    }
+}
+
+/// Access granted to an opened file.
+#[derive(Clone, Copy)]
+pub(crate) enum Access {
+    ReadOnly,
+    ReadWrite,
+    WriteOnly,
+}
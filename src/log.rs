@@ -0,0 +1,63 @@
+//! Messages of the hook, written to stderr as `[Hook] ERROR: ...` or
+//! `[Hook] WARNING: ...` with the `error!` and `warning!` macros. Which ones
+//! are written is set in the `[logging]` table of the configuration file (see
+//! the `settings` module).
+
+use std::fmt;
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+use crate::settings;
+
+/// Write an error message.
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::LogLevel::Error, format_args!($($arg)*))
+    };
+}
+
+/// Write a warning message.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::LogLevel::Warning, format_args!($($arg)*))
+    };
+}
+
+/// The least severe messages that are written, as `warning` (the default),
+/// `error` or `off` (none).
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogLevel {
+    #[default]
+    Warning,
+    Error,
+    Off,
+}
+
+impl LogLevel {
+    /// Whether messages of `self` severity are written at the `level` log
+    /// level.
+    pub fn is_shown_at(self, level: LogLevel) -> bool {
+        self >= level
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            LogLevel::Warning => "WARNING",
+            LogLevel::Error => "ERROR",
+            LogLevel::Off => "OFF",
+        }
+    }
+}
+
+/// Write `message` if messages of `severity` are shown. Until the
+/// configuration file has been loaded (which happens before tracking any
+/// file), every message is.
+pub fn write(severity: LogLevel, message: fmt::Arguments) {
+    let level =
+        Lazy::get(&settings::SETTINGS).map_or(LogLevel::Warning, |settings| settings.log_level);
+    if severity.is_shown_at(level) {
+        eprintln!("[Hook] {}: {message}", severity.label());
+    }
+}
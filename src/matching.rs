@@ -0,0 +1,146 @@
+//! Identification of the auth files among all the opened files.
+//!
+//! By default, the path of every opened file is compared byte by byte with the
+//! path of each auth file (see `params::AuthFileMatching`). Alternatively,
+//! files can be matched by identity: the auth files are stat'ed when the hook
+//! is loaded, and then every opened file is considered an auth file if it has
+//! the same device and inode numbers. This way the file is recognized
+//! however its path is spelled (relative, absolute, through symlinks, etc.).
+//! When the identity of either file can't be obtained, their canonicalized
+//! paths are compared instead.
+
+use std::{
+    ffi::{CStr, OsStr},
+    fs,
+    mem::MaybeUninit,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+};
+
+use libc::{c_char, c_int};
+use once_cell::sync::Lazy;
+
+use crate::{
+    memory_files,
+    params::{self, AuthFile, AuthFileMatching},
+};
+
+/// Device and inode numbers of each of the auth files (in the same order as
+/// `params::AUTH_FILES`), if they could be stat'ed when the hook was loaded
+/// (see `init`). In-memory auth files don't have any, as their in-memory files
+/// are only created once needed (see `auth_file_identity`).
+static AUTH_FILE_IDENTITIES: Lazy<Vec<Option<FileIdentity>>> = Lazy::new(|| {
+    params::AUTH_FILES
+        .iter()
+        .map(|auth_file| {
+            if auth_file.is_in_memory() {
+                None
+            } else {
+                stat_auth_file(auth_file)
+            }
+        })
+        .collect()
+});
+
+/// Stat the auth files right away, so that their identities are the ones they
+/// had at startup. Otherwise, an auth file replaced or moved before OpenVPN
+/// first opens a file would be identified by whatever is at its path then.
+pub fn init() {
+    Lazy::force(&AUTH_FILE_IDENTITIES);
+}
+
+/// Device and inode numbers of a file, which identify it uniquely.
+pub type FileIdentity = (u64, u64);
+
+/// The auth file that `path` (which has been opened as `fd`) is, if any,
+/// according to the configured matching mode.
+///
+/// `path` must be either absolute or relative to the current directory.
+pub unsafe fn auth_file(path: *const c_char, fd: c_int) -> Option<&'static AuthFile> {
+    match *params::AUTH_FILE_MATCHING {
+        AuthFileMatching::Path => {
+            let path = CStr::from_ptr(path).to_bytes();
+            params::AUTH_FILES
+                .iter()
+                .find(|auth_file| is_auth_file_path(path, auth_file))
+        }
+        AuthFileMatching::Inode => {
+            let identity = descriptor_identity(fd);
+            params::AUTH_FILES
+                .iter()
+                .zip(AUTH_FILE_IDENTITIES.iter())
+                .find(|(auth_file, startup_identity)| {
+                    match (auth_file_identity(auth_file, startup_identity), identity) {
+                        (Some(auth_file_identity), Some(identity)) => {
+                            identity == auth_file_identity
+                        }
+                        _ => is_auth_file_canonical_path(path, auth_file),
+                    }
+                })
+                .map(|(auth_file, _)| auth_file)
+        }
+    }
+}
+
+/// The auth file that the file descriptor `fd` refers to, if any, which is
+/// the case if both have the same device and inode numbers. Used when there is
+/// no path to compare against, whatever the matching mode.
+pub fn auth_file_descriptor(fd: c_int) -> Option<&'static AuthFile> {
+    let identity = descriptor_identity(fd)?;
+    params::AUTH_FILES
+        .iter()
+        .zip(AUTH_FILE_IDENTITIES.iter())
+        .find(|(auth_file, startup_identity)| {
+            auth_file_identity(auth_file, startup_identity).or_else(|| stat_auth_file(auth_file))
+                == Some(identity)
+        })
+        .map(|(auth_file, _)| auth_file)
+}
+
+/// Whether `path` is the path of `auth_file`, byte by byte (without decoding
+/// it, as this is done for every opened file).
+fn is_auth_file_path(path: &[u8], auth_file: &AuthFile) -> bool {
+    path == auth_file.path.as_bytes()
+}
+
+/// Whether `path` and the path of `auth_file` are the same once
+/// canonicalized.
+unsafe fn is_auth_file_canonical_path(path: *const c_char, auth_file: &AuthFile) -> bool {
+    let path = OsStr::from_bytes(CStr::from_ptr(path).to_bytes());
+    match (fs::canonicalize(path), fs::canonicalize(&auth_file.path)) {
+        (Ok(path), Ok(auth_file_path)) => path == auth_file_path,
+        _ => false,
+    }
+}
+
+/// Identity of `auth_file`: the one it had at startup (`startup_identity`), or
+/// the one of its in-memory file, if it is stored in memory.
+fn auth_file_identity(
+    auth_file: &AuthFile,
+    startup_identity: &Option<FileIdentity>,
+) -> Option<FileIdentity> {
+    if auth_file.is_in_memory() {
+        memory_files::identity(auth_file)
+    } else {
+        *startup_identity
+    }
+}
+
+/// Identity of `auth_file`, if it can be stat'ed (or of its in-memory file,
+/// if it is stored in memory).
+fn stat_auth_file(auth_file: &AuthFile) -> Option<FileIdentity> {
+    if auth_file.is_in_memory() {
+        return memory_files::identity(auth_file);
+    }
+    let metadata = fs::metadata(&auth_file.path).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Identity of the file referred to by `fd`, if it can be stat'ed.
+pub fn descriptor_identity(fd: c_int) -> Option<FileIdentity> {
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    if unsafe { libc::fstat(fd, stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let stat = unsafe { stat.assume_init() };
+    Some((stat.st_dev, stat.st_ino))
+}
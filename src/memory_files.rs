@@ -0,0 +1,205 @@
+//! In-memory auth files, for hosts that don't store them on disk at all (see
+//! `params::AUTH_FILE_STORAGE`).
+//!
+//! The first time the path of an auth file is used, an anonymous in-memory
+//! file (`memfd_create`) is created for it, holding the username
+//! line (unless the secret has its own username, or the file has no username
+//! line) and an empty password line.
+//! The files are made read-only and sealed, so nobody can change them.
+//!
+//! From then on, every function that takes the path of an auth file (`fopen`,
+//! `open`, `stat`, `access`, etc.) is called with the `/proc/self/fd` path of
+//! its in-memory file instead. The file is then tracked like any other auth
+//! file, so the password (and the username, if embedded) is only ever served
+//! from the virtual content of the opened streams and file descriptors (see
+//! the `content` module), and never written to the in-memory file itself.
+
+use std::{
+    ffi::{CStr, CString},
+    io, ptr,
+};
+
+use libc::{c_char, c_int, c_uint, AT_FDCWD};
+use once_cell::sync::{Lazy, OnceCell};
+
+use crate::{
+    functions::Functions,
+    guard,
+    matching::{self, FileIdentity},
+    params::{self, AuthFile, AuthFileStorage},
+};
+
+/// The in-memory file of each of the auth files (in the same order as
+/// `params::AUTH_FILES`), once it has been needed, if it could be created.
+static MEMORY_FILES: Lazy<Vec<OnceCell<Option<MemoryFile>>>> =
+    Lazy::new(|| params::AUTH_FILES.iter().map(|_| OnceCell::new()).collect());
+
+/// An auth file that only exists in memory.
+struct MemoryFile {
+    /// The file descriptor of the in-memory file, which is kept open for the
+    /// lifetime of the process.
+    fd: c_int,
+    /// Its identity, to detect that `fd` has been closed (and maybe reused)
+    /// behind our back.
+    identity: FileIdentity,
+    /// Path through which it can be opened (`/proc/self/fd/<fd>`).
+    path: CString,
+}
+
+impl MemoryFile {
+    /// The in-memory file of the auth file at `index` in `params::AUTH_FILES`,
+    /// created the first time it is needed.
+    fn get(index: usize) -> Option<&'static MemoryFile> {
+        MEMORY_FILES[index]
+            .get_or_init(|| {
+                let auth_file = &params::AUTH_FILES[index];
+                MemoryFile::create(auth_file)
+                    .map_err(|err| {
+                        error!(
+                            "Unable to create the in-memory auth file '{}': {err}",
+                            auth_file.path
+                        );
+                    })
+                    .ok()
+            })
+            .as_ref()
+    }
+
+    fn create(auth_file: &AuthFile) -> io::Result<Self> {
+        let contents = if auth_file.kind.username_line().is_none() {
+            // Just the (empty) password line
+            "\n".to_owned()
+        } else if auth_file.username_line().is_some() {
+            // Replaced anyway
+            "\n\n".to_owned()
+        } else {
+            let username = params::AUTH_FILE_USERNAME.as_deref().ok_or_else(|| {
+                io::Error::other(format!(
+                    "No username for the '{}' secret. Set AUTH_FILE_USERNAME",
+                    auth_file.secret
+                ))
+            })?;
+            format!("{username}\n\n")
+        };
+        let fd = unsafe {
+            libc::memfd_create(
+                c"auth-user-pass".as_ptr(),
+                libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+            )
+        };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let memory_file = initialize(fd, contents.as_bytes())
+            .and_then(|()| matching::descriptor_identity(fd).ok_or_else(io::Error::last_os_error))
+            .map(|identity| MemoryFile {
+                fd,
+                identity,
+                path: CString::new(format!("/proc/self/fd/{fd}"))
+                    // Safe to unwrap, as we know the string doesn't have any null bytes
+                    .unwrap(),
+            });
+        if memory_file.is_err() {
+            Functions::close(fd);
+        }
+        memory_file
+    }
+}
+
+/// Write `contents` to the in-memory file `fd`, and then make it read-only
+/// and seal it.
+fn initialize(fd: c_int, contents: &[u8]) -> io::Result<()> {
+    let mut written = 0;
+    while written < contents.len() {
+        let n = unsafe {
+            libc::write(
+                fd,
+                contents[written..].as_ptr().cast(),
+                contents.len() - written,
+            )
+        };
+        match n {
+            n if n > 0 => written += n as usize,
+            _ if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            _ => return Err(io::Error::last_os_error()),
+        }
+    }
+    let seals: c_uint =
+        (libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE)
+            as c_uint;
+    if unsafe { libc::fchmod(fd, libc::S_IRUSR) } != 0
+        || unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Call `f` (the original function called by the hook named `name`) with
+/// `path` (relative to the `dirfd` directory), or with the path of its
+/// in-memory file if it is one of the auth files. See `redirected`.
+///
+/// # Safety
+///
+/// `path` must be null or a valid C string.
+pub unsafe fn with_path<R>(
+    name: &str,
+    dirfd: c_int,
+    path: *const c_char,
+    f: impl FnOnce(*const c_char) -> R,
+) -> R {
+    match redirected(name, dirfd, path) {
+        Some(redirected) => f(redirected.as_ptr()),
+        None => f(path),
+    }
+}
+
+/// The path of the in-memory file to use instead of `path` (relative to the
+/// `dirfd` directory), if the auth files are stored in memory and `path` is
+/// one of them (compared byte by byte, like with `AUTH_FILE_MATCHING=path`),
+/// other than the encrypted files. `name` is the name of the hook.
+///
+/// # Safety
+///
+/// `path` must be null or a valid C string.
+pub unsafe fn redirected(name: &str, dirfd: c_int, path: *const c_char) -> Option<CString> {
+    if path.is_null() {
+        return None;
+    }
+    // The storage is checked inside the hook logic, as loading the settings
+    // may open files too
+    guard::catch_panic(name, || {
+        if *params::AUTH_FILE_STORAGE != AuthFileStorage::Memory {
+            return None;
+        }
+        if dirfd != AT_FDCWD && *path != b'/' as c_char {
+            return None;
+        }
+        let path = CStr::from_ptr(path).to_bytes();
+        let index = params::AUTH_FILES
+            .iter()
+            .position(|auth_file| auth_file.is_in_memory() && auth_file.path.as_bytes() == path)?;
+        let memory_file = MemoryFile::get(index)?;
+        if matching::descriptor_identity(memory_file.fd) != Some(memory_file.identity) {
+            error!(
+                "The in-memory auth file '{}' has been closed",
+                params::AUTH_FILES[index].path
+            );
+            return None;
+        }
+        Some(memory_file.path.clone())
+    })
+    .flatten()
+}
+
+/// Identity of the in-memory file of `auth_file`, if it is stored in memory
+/// and it could be created.
+pub fn identity(auth_file: &AuthFile) -> Option<FileIdentity> {
+    if !auth_file.is_in_memory() {
+        return None;
+    }
+    let index = params::AUTH_FILES
+        .iter()
+        .position(|candidate| ptr::eq(candidate, auth_file))?;
+    MemoryFile::get(index).map(|memory_file| memory_file.identity)
+}
@@ -0,0 +1,310 @@
+//! Replacements for the functions that query files by path without opening
+//! them (`stat`, `access`, etc.).
+//!
+//! They only matter when the auth files are stored in memory (see the
+//! `memory_files` module): then, the paths of the auth files don't exist on
+//! disk, and these functions are answered by the in-memory files instead, so
+//! that they are consistent with opening them. For every other path (and
+//! when the auth files are on disk) the original functions are called as is.
+//!
+//! The in-memory files are reached through their `/proc/self/fd` symlinks, so
+//! the `lstat` family (and `AT_SYMLINK_NOFOLLOW`) must follow them, as the
+//! auth files are not symlinks themselves.
+//!
+//! glibc 2.33 turned `stat` and friends into real functions. Programs built
+//! against older versions call the `__xstat` family instead, so both are
+//! replaced.
+
+use libc::{c_char, c_int, c_uint, AT_FDCWD, AT_SYMLINK_NOFOLLOW};
+
+use crate::{functions::Functions, memory_files};
+
+/// Replacement for the `stat` libc function.
+///
+/// # Safety
+///
+/// `path` must be a valid C string, and `buf` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn stat(path: *const c_char, buf: *mut libc::stat) -> c_int {
+    memory_files::with_path("stat", AT_FDCWD, path, |path| Functions::stat(path, buf))
+}
+
+/// Replacement for the `stat64` libc function. See `stat`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string, and `buf` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn stat64(path: *const c_char, buf: *mut libc::stat64) -> c_int {
+    memory_files::with_path("stat64", AT_FDCWD, path, |path| {
+        Functions::stat64(path, buf)
+    })
+}
+
+/// Replacement for the `lstat` libc function. See `stat`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string, and `buf` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lstat(path: *const c_char, buf: *mut libc::stat) -> c_int {
+    match memory_files::redirected("lstat", AT_FDCWD, path) {
+        Some(redirected) => Functions::stat(redirected.as_ptr(), buf),
+        None => Functions::lstat(path, buf),
+    }
+}
+
+/// Replacement for the `lstat64` libc function. See `stat`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string, and `buf` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lstat64(path: *const c_char, buf: *mut libc::stat64) -> c_int {
+    match memory_files::redirected("lstat64", AT_FDCWD, path) {
+        Some(redirected) => Functions::stat64(redirected.as_ptr(), buf),
+        None => Functions::lstat64(path, buf),
+    }
+}
+
+/// Replacement for the `fstatat` libc function. See `stat`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string, and `buf` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn fstatat(
+    dirfd: c_int,
+    path: *const c_char,
+    buf: *mut libc::stat,
+    flags: c_int,
+) -> c_int {
+    match memory_files::redirected("fstatat", dirfd, path) {
+        Some(redirected) => Functions::fstatat(
+            dirfd,
+            redirected.as_ptr(),
+            buf,
+            flags & !AT_SYMLINK_NOFOLLOW,
+        ),
+        None => Functions::fstatat(dirfd, path, buf, flags),
+    }
+}
+
+/// Replacement for the `fstatat64` libc function. See `stat`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string, and `buf` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn fstatat64(
+    dirfd: c_int,
+    path: *const c_char,
+    buf: *mut libc::stat64,
+    flags: c_int,
+) -> c_int {
+    match memory_files::redirected("fstatat64", dirfd, path) {
+        Some(redirected) => Functions::fstatat64(
+            dirfd,
+            redirected.as_ptr(),
+            buf,
+            flags & !AT_SYMLINK_NOFOLLOW,
+        ),
+        None => Functions::fstatat64(dirfd, path, buf, flags),
+    }
+}
+
+/// Replacement for the `__xstat` glibc function, what `stat` was before glibc
+/// 2.33. See `stat`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string, and `buf` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn __xstat(ver: c_int, path: *const c_char, buf: *mut libc::stat) -> c_int {
+    memory_files::with_path("__xstat", AT_FDCWD, path, |path| {
+        Functions::xstat(ver, path, buf)
+    })
+}
+
+/// Replacement for the `__xstat64` glibc function. See `__xstat`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string, and `buf` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn __xstat64(
+    ver: c_int,
+    path: *const c_char,
+    buf: *mut libc::stat64,
+) -> c_int {
+    memory_files::with_path("__xstat64", AT_FDCWD, path, |path| {
+        Functions::xstat64(ver, path, buf)
+    })
+}
+
+/// Replacement for the `__lxstat` glibc function, what `lstat` was before
+/// glibc 2.33. See `stat`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string, and `buf` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn __lxstat(ver: c_int, path: *const c_char, buf: *mut libc::stat) -> c_int {
+    match memory_files::redirected("__lxstat", AT_FDCWD, path) {
+        Some(redirected) => Functions::xstat(ver, redirected.as_ptr(), buf),
+        None => Functions::lxstat(ver, path, buf),
+    }
+}
+
+/// Replacement for the `__lxstat64` glibc function. See `__lxstat`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string, and `buf` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn __lxstat64(
+    ver: c_int,
+    path: *const c_char,
+    buf: *mut libc::stat64,
+) -> c_int {
+    match memory_files::redirected("__lxstat64", AT_FDCWD, path) {
+        Some(redirected) => Functions::xstat64(ver, redirected.as_ptr(), buf),
+        None => Functions::lxstat64(ver, path, buf),
+    }
+}
+
+/// Replacement for the `__fxstatat` glibc function, what `fstatat` was before
+/// glibc 2.33. See `stat`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string, and `buf` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn __fxstatat(
+    ver: c_int,
+    dirfd: c_int,
+    path: *const c_char,
+    buf: *mut libc::stat,
+    flags: c_int,
+) -> c_int {
+    match memory_files::redirected("__fxstatat", dirfd, path) {
+        Some(redirected) => Functions::fxstatat(
+            ver,
+            dirfd,
+            redirected.as_ptr(),
+            buf,
+            flags & !AT_SYMLINK_NOFOLLOW,
+        ),
+        None => Functions::fxstatat(ver, dirfd, path, buf, flags),
+    }
+}
+
+/// Replacement for the `__fxstatat64` glibc function. See `__fxstatat`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string, and `buf` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn __fxstatat64(
+    ver: c_int,
+    dirfd: c_int,
+    path: *const c_char,
+    buf: *mut libc::stat64,
+    flags: c_int,
+) -> c_int {
+    match memory_files::redirected("__fxstatat64", dirfd, path) {
+        Some(redirected) => Functions::fxstatat64(
+            ver,
+            dirfd,
+            redirected.as_ptr(),
+            buf,
+            flags & !AT_SYMLINK_NOFOLLOW,
+        ),
+        None => Functions::fxstatat64(ver, dirfd, path, buf, flags),
+    }
+}
+
+/// Replacement for the `statx` glibc function. See `stat`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string, and `buf` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn statx(
+    dirfd: c_int,
+    path: *const c_char,
+    flags: c_int,
+    mask: c_uint,
+    buf: *mut libc::statx,
+) -> c_int {
+    match memory_files::redirected("statx", dirfd, path) {
+        Some(redirected) => Functions::statx(
+            dirfd,
+            redirected.as_ptr(),
+            flags & !AT_SYMLINK_NOFOLLOW,
+            mask,
+            buf,
+        ),
+        None => Functions::statx(dirfd, path, flags, mask, buf),
+    }
+}
+
+/// Replacement for the `access` libc function. The in-memory auth files are
+/// only readable by their owner.
+///
+/// # Safety
+///
+/// `path` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn access(path: *const c_char, mode: c_int) -> c_int {
+    memory_files::with_path("access", AT_FDCWD, path, |path| {
+        Functions::access(path, mode)
+    })
+}
+
+/// Replacement for the `faccessat` libc function. See `access`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn faccessat(
+    dirfd: c_int,
+    path: *const c_char,
+    mode: c_int,
+    flags: c_int,
+) -> c_int {
+    match memory_files::redirected("faccessat", dirfd, path) {
+        Some(redirected) => Functions::faccessat(
+            dirfd,
+            redirected.as_ptr(),
+            mode,
+            flags & !AT_SYMLINK_NOFOLLOW,
+        ),
+        None => Functions::faccessat(dirfd, path, mode, flags),
+    }
+}
+
+/// Replacement for the `euidaccess` glibc function. See `access`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn euidaccess(path: *const c_char, mode: c_int) -> c_int {
+    memory_files::with_path("euidaccess", AT_FDCWD, path, |path| {
+        Functions::euidaccess(path, mode)
+    })
+}
+
+/// Replacement for the `eaccess` glibc function, an alias of `euidaccess`.
+///
+/// # Safety
+///
+/// `path` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn eaccess(path: *const c_char, mode: c_int) -> c_int {
+    memory_files::with_path("eaccess", AT_FDCWD, path, |path| {
+        Functions::eaccess(path, mode)
+    })
+}
@@ -3,55 +3,594 @@
 
 use encryption::decrypt;
 use once_cell::sync::Lazy;
+use serde::Deserialize;
 use std::{env, error::Error, ffi::CString};
 
+use crate::{
+    discovery::{self, Discovered},
+    settings::SETTINGS,
+    PASSPHRASE_LINE_NUMBER, PASSWORD_LINE_NUMBER, USERNAME_LINE_NUMBER,
+};
+
 /// Path for the file that contains the VPN connection username and password
-/// (one per line).
+/// (one per line), which gets the `default` secret (the password given at
+/// compile time in `BUILD_ARG_PASSWORD`), or the one in `[secrets]` of the
+/// configuration file. If set, it overrides the `auth-user-pass` file found by
+/// `AUTH_FILE_DISCOVERY`.
 ///
 /// It needs to be specified here in exactly the same way as it is specified in
 /// the `auth-user-pass` OpenVPN configuration file (or `--auth-user-pass`
 /// command line argument). E.g., if it is specified as a relative path there,
 /// it should be specified as the same relative path here. We make a simple
-/// string comparison to identify that the auth file is being opened.
-pub static AUTH_FILE_PATH: Lazy<Option<String>> = Lazy::new(|| match env::var("AUTH_FILE_PATH") {
-    Ok(path) => Some(path),
-    Err(env::VarError::NotPresent) => {
-        eprintln!("[Hook] ERROR: The environment variable AUTH_FILE_PATH is not set");
-        None
-    }
-    Err(env::VarError::NotUnicode(_)) => {
-        eprintln!(
-            "[Hook] ERROR: The environment variable AUTH_FILE_PATH is not a valid UTF-8 string"
+/// string comparison to identify that the auth file is being opened, unless
+/// `AUTH_FILE_MATCHING` says otherwise.
+static AUTH_FILE_PATH: Lazy<Option<String>> = Lazy::new(|| optional_var("AUTH_FILE_PATH"));
+
+/// Kind of the file in `AUTH_FILE_PATH`. Set with the `AUTH_FILE_KIND`
+/// environment variable, as `auth-user-pass` (the default), `askpass`,
+/// `http-proxy`, `socks-proxy` or `config`.
+static AUTH_FILE_KIND: Lazy<AuthFileKind> = Lazy::new(|| match optional_var("AUTH_FILE_KIND") {
+    None => AuthFileKind::UserPass,
+    Some(kind) => AuthFileKind::parse(&kind).unwrap_or_else(|| {
+        error!(
+            "The environment variable AUTH_FILE_KIND must be either \
+             'auth-user-pass', 'askpass', 'http-proxy', 'socks-proxy' or 'config'. \
+             Using 'auth-user-pass'"
+        );
+        AuthFileKind::UserPass
+    }),
+});
+
+/// Path for the authfile of the proxy that OpenVPN connects through (the
+/// `authfile` argument of the `http-proxy` or `socks-proxy` directives), which
+/// gets the `proxy` secret (the credentials given at compile time in
+/// `BUILD_ARG_PROXY_USERNAME` and `BUILD_ARG_PROXY_PASSWORD`). It is matched
+/// like `AUTH_FILE_PATH`, and overrides the proxy authfile found by
+/// `AUTH_FILE_DISCOVERY`.
+static PROXY_AUTH_FILE_PATH: Lazy<Option<String>> =
+    Lazy::new(|| optional_var("PROXY_AUTH_FILE_PATH"));
+
+/// All the auth files to track, each with the name of the secret it gets:
+/// the ones in `AUTH_FILE_PATH` and `PROXY_AUTH_FILE_PATH` (or the ones found
+/// by `AUTH_FILE_DISCOVERY`, if any), then the ones in `AUTH_FILES`, then the
+/// encrypted files in `ENCRYPTED_FILES`, and then the `files` of the
+/// configuration file (see the `settings` module). When several have the
+/// same path, the first one wins.
+///
+/// `AUTH_FILES` is a list of `secret=path` entries separated by `:` (e.g.,
+/// `office=/etc/openvpn/office.txt:lab=/etc/openvpn/lab.txt`), where every
+/// secret must have been embedded at compile time (see `build.rs`). Paths are
+/// matched exactly like `AUTH_FILE_PATH`. The secret can be prefixed with the
+/// kind of the file and a `/` (e.g., `askpass/key=/etc/openvpn/key.txt`), as
+/// they are `auth-user-pass` files otherwise.
+///
+/// `ENCRYPTED_FILES` is a list of files separated by `:` that were encrypted
+/// with `openvpn-auth-hook-seal`, which are decrypted when read. Files without
+/// credentials to replace (e.g., `key`, `tls-crypt` or `pkcs12` files, or
+/// whole configuration files) are given by their path alone. Files with
+/// credentials are given like in `AUTH_FILES`, and their credentials are
+/// replaced once decrypted (e.g., `config/office=/etc/openvpn/office.conf`).
+pub static AUTH_FILES: Lazy<Vec<AuthFile>> = Lazy::new(|| {
+    let discovered = match *AUTH_FILE_DISCOVERY {
+        AuthFileDiscovery::None => Some(Discovered::default()),
+        AuthFileDiscovery::OpenVpn => discovery::discover(),
+    };
+    // One of the processes run by OpenVPN (e.g., an `up` script)
+    let other_process = discovered.is_none();
+    let discovered = discovered.unwrap_or_default();
+    let mut auth_files: Vec<AuthFile> = AUTH_FILE_PATH
+        .clone()
+        .map(|path| (*AUTH_FILE_KIND, path))
+        .or(discovered.auth_user_pass)
+        .into_iter()
+        .map(|(kind, path)| AuthFile {
+            path,
+            secret: SETTINGS.auth_user_pass_secret.unwrap_or(DEFAULT_SECRET),
+            kind,
+            encrypted: false,
+        })
+        .collect();
+    if let Some(path) = discovered.askpass {
+        let secret = SETTINGS.askpass_secret.unwrap_or(ASKPASS_SECRET);
+        if find_secret(secret).is_ok() {
+            auth_files.push(AuthFile {
+                path,
+                secret,
+                kind: AuthFileKind::Askpass,
+                encrypted: false,
+            });
+        } else {
+            error!(
+                "No '{secret}' secret was embedded at compile time (see \
+                 BUILD_ARG_SECRET_NAMES). Ignoring the askpass file '{path}'"
+            );
+        }
+    }
+    let proxy = PROXY_AUTH_FILE_PATH
+        .clone()
+        .map(|path| (AuthFileKind::HttpProxy, path, "PROXY_AUTH_FILE_PATH"))
+        .or(discovered
+            .proxy
+            .map(|(kind, path)| (kind, path, "the proxy authfile")));
+    if let Some((kind, path, source)) = proxy {
+        let secret = SETTINGS.proxy_secret.unwrap_or(PROXY_SECRET);
+        if find_secret(secret).is_ok() {
+            auth_files.push(AuthFile {
+                path,
+                secret,
+                kind,
+                encrypted: false,
+            });
+        } else {
+            error!(
+                "No proxy credentials were embedded at compile time (see \
+                 BUILD_ARG_PROXY_PASSWORD). Ignoring {source} '{path}'"
+            );
+        }
+    }
+    let entries = optional_var("AUTH_FILES");
+    if let Some(entries) = &entries {
+        auth_files.extend(
+            entries
+                .split(':')
+                .filter_map(|entry| parse_auth_file(entry, "AUTH_FILES")),
         );
-        None
     }
+    let encrypted_paths = optional_var("ENCRYPTED_FILES");
+    if auth_files.is_empty()
+        && entries.is_none()
+        && encrypted_paths.is_none()
+        && SETTINGS.files.is_empty()
+    {
+        match *AUTH_FILE_DISCOVERY {
+            AuthFileDiscovery::None => {
+                error!("The environment variable AUTH_FILE_PATH is not set")
+            }
+            AuthFileDiscovery::OpenVpn if !other_process => {
+                error!("No auth files were found in the options of OpenVPN")
+            }
+            AuthFileDiscovery::OpenVpn => {}
+        }
+    }
+    if let Some(paths) = encrypted_paths {
+        auth_files.extend(
+            paths
+                .split(':')
+                .filter(|entry| !entry.is_empty())
+                .filter_map(parse_encrypted_file),
+        );
+    }
+    auth_files.extend(SETTINGS.files.iter().cloned());
+    auth_files
 });
 
-/// OpenVPN connection password. It will be injected when OpenVPN reads the auth
-/// file, making it believe the password was actually written in the second line
-/// of the file.
+/// An auth file to track.
+#[derive(Clone)]
+pub struct AuthFile {
+    /// Path of the file, to compare with the opened files.
+    pub path: String,
+    /// Name of the secret that replaces its password line (and its username
+    /// line, if the secret has a username). `NO_SECRET` for opaque files.
+    pub secret: &'static str,
+    /// Which lines the secret replaces.
+    pub kind: AuthFileKind,
+    /// Whether the file was encrypted with `openvpn-auth-hook-seal`, and must
+    /// be decrypted before replacing its lines (see `ENCRYPTED_FILES`).
+    pub encrypted: bool,
+}
+
+impl AuthFile {
+    /// Number of the line replaced with the password of the secret.
+    pub fn password_line(&self) -> usize {
+        self.kind.password_line()
+    }
+
+    /// Number of the line replaced with the username of the secret, if the
+    /// file has a username line and the secret has a username.
+    pub fn username_line(&self) -> Option<usize> {
+        self.kind
+            .username_line()
+            .filter(|_| has_username(self.secret))
+    }
+
+    /// Number of the first line replaced in the file. Encrypted and
+    /// configuration files are rewritten as a whole, so they are from the
+    /// first line.
+    pub fn first_replaced_line(&self) -> usize {
+        if self.encrypted || self.kind == AuthFileKind::Config {
+            return 1;
+        }
+        self.username_line()
+            .map_or(self.password_line(), |line| line.min(self.password_line()))
+    }
+
+    /// Whether the file is served from memory instead of from disk (see
+    /// `AUTH_FILE_STORAGE`). Encrypted and configuration files are always on
+    /// disk.
+    pub fn is_in_memory(&self) -> bool {
+        *AUTH_FILE_STORAGE == AuthFileStorage::Memory
+            && !self.encrypted
+            && !matches!(self.kind, AuthFileKind::Opaque | AuthFileKind::Config)
+    }
+}
+
+/// The kinds of files that OpenVPN reads credentials from, named after their
+/// directives.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthFileKind {
+    /// `auth-user-pass`: the username in the first line and the password in
+    /// the second one.
+    #[serde(rename = "auth-user-pass")]
+    UserPass,
+    /// `askpass`: the private key passphrase in the first (and only) line.
+    Askpass,
+    /// The `http-proxy` authfile: laid out like the `auth-user-pass` file.
+    HttpProxy,
+    /// The `socks-proxy` authfile: laid out like the `auth-user-pass` file.
+    SocksProxy,
+    /// An OpenVPN configuration file with the credentials inline, in an
+    /// `<auth-user-pass>` block laid out like the `auth-user-pass` file (see
+    /// the `config` module). Its line numbers are the ones inside the block.
+    Config,
+    /// A file without credentials to replace (e.g., a private key), listed
+    /// in `ENCRYPTED_FILES` by its path alone, which is only decrypted. It
+    /// has no lines of its own, so its first line counts as the replaced one.
+    #[serde(skip)]
+    Opaque,
+}
+
+impl AuthFileKind {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "auth-user-pass" => Some(AuthFileKind::UserPass),
+            "askpass" => Some(AuthFileKind::Askpass),
+            "http-proxy" => Some(AuthFileKind::HttpProxy),
+            "socks-proxy" => Some(AuthFileKind::SocksProxy),
+            "config" => Some(AuthFileKind::Config),
+            _ => None,
+        }
+    }
+
+    fn password_line(self) -> usize {
+        match self {
+            AuthFileKind::UserPass
+            | AuthFileKind::HttpProxy
+            | AuthFileKind::SocksProxy
+            | AuthFileKind::Config => PASSWORD_LINE_NUMBER,
+            AuthFileKind::Askpass => PASSPHRASE_LINE_NUMBER,
+            AuthFileKind::Opaque => 1,
+        }
+    }
+
+    /// Number of the username line, if the file has one.
+    pub fn username_line(self) -> Option<usize> {
+        match self {
+            AuthFileKind::UserPass
+            | AuthFileKind::HttpProxy
+            | AuthFileKind::SocksProxy
+            | AuthFileKind::Config => Some(USERNAME_LINE_NUMBER),
+            AuthFileKind::Askpass | AuthFileKind::Opaque => None,
+        }
+    }
+}
+
+/// Parse an `ENCRYPTED_FILES` entry: either the path of an opaque file, or an
+/// entry like the `AUTH_FILES` ones.
+fn parse_encrypted_file(entry: &str) -> Option<AuthFile> {
+    let auth_file = if entry.contains('=') {
+        parse_auth_file(entry, "ENCRYPTED_FILES")?
+    } else {
+        AuthFile {
+            path: entry.to_owned(),
+            secret: NO_SECRET,
+            kind: AuthFileKind::Opaque,
+            encrypted: false,
+        }
+    };
+    Some(AuthFile {
+        encrypted: true,
+        ..auth_file
+    })
+}
+
+/// Parse an `AUTH_FILES` entry (or an entry like it, in the environment
+/// variable `variable`).
+fn parse_auth_file(entry: &str, variable: &str) -> Option<AuthFile> {
+    let Some((name, path)) = entry.split_once('=') else {
+        error!("Invalid entry in {variable}: '{entry}'. It must be 'secret=path'");
+        return None;
+    };
+    let (kind, name) = match name.split_once('/') {
+        Some((kind_name, name)) => {
+            let Some(kind) = AuthFileKind::parse(kind_name) else {
+                error!("Unknown kind '{kind_name}' in {variable}. Ignoring '{path}'");
+                return None;
+            };
+            (kind, name)
+        }
+        None => (AuthFileKind::UserPass, name),
+    };
+    let Some(secret) = SECRETS.iter().find(|secret| secret.name == name) else {
+        error!("Unknown secret '{name}' in {variable}. Ignoring '{path}'");
+        return None;
+    };
+    Some(AuthFile {
+        path: path.to_owned(),
+        secret: secret.name,
+        kind,
+        encrypted: false,
+    })
+}
+
+/// The value of the environment variable `name`, if set (and valid).
+fn optional_var(name: &str) -> Option<String> {
+    match env::var(name) {
+        Ok(value) => Some(value),
+        Err(env::VarError::NotPresent) => None,
+        Err(env::VarError::NotUnicode(_)) => {
+            error!("The environment variable {name} is not a valid UTF-8 string");
+            None
+        }
+    }
+}
+
+/// Whether to find the auth files in the options of OpenVPN (see the
+/// `discovery` module). Set with the `AUTH_FILE_DISCOVERY` environment
+/// variable (or `discovery` in the configuration file), as `none` (the
+/// default) or `openvpn`.
+static AUTH_FILE_DISCOVERY: Lazy<AuthFileDiscovery> =
+    Lazy::new(|| match env::var("AUTH_FILE_DISCOVERY").as_deref() {
+        Err(env::VarError::NotPresent) => SETTINGS.discovery.unwrap_or(AuthFileDiscovery::None),
+        Ok("none") => AuthFileDiscovery::None,
+        Ok("openvpn") => AuthFileDiscovery::OpenVpn,
+        _ => {
+            error!(
+                "The environment variable AUTH_FILE_DISCOVERY must be either \
+                 'none' or 'openvpn'. Using 'none'"
+            );
+            AuthFileDiscovery::None
+        }
+    });
+
+/// Where the auth files come from, besides the environment variables and the
+/// configuration file. See
+/// `AUTH_FILE_DISCOVERY`.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthFileDiscovery {
+    /// Only from the environment variables (and the configuration file).
+    None,
+    /// Also from the options of OpenVPN: its command line and configuration
+    /// files.
+    #[serde(rename = "openvpn")]
+    OpenVpn,
+}
+
+/// How to identify the auth file among all the opened files. Set with the
+/// `AUTH_FILE_MATCHING` environment variable (or `matching` in the
+/// configuration file), as `path` (the default) or `inode`.
+pub static AUTH_FILE_MATCHING: Lazy<AuthFileMatching> =
+    Lazy::new(|| match env::var("AUTH_FILE_MATCHING").as_deref() {
+        Err(env::VarError::NotPresent) => SETTINGS.matching.unwrap_or(AuthFileMatching::Path),
+        Ok("path") => AuthFileMatching::Path,
+        Ok("inode") => AuthFileMatching::Inode,
+        _ => {
+            error!(
+                "The environment variable AUTH_FILE_MATCHING must be either \
+                 'path' or 'inode'. Using 'path'"
+            );
+            AuthFileMatching::Path
+        }
+    });
+
+/// Ways to identify the auth file. See `AUTH_FILE_MATCHING`.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthFileMatching {
+    /// The path passed to `fopen` (or `open`, etc.) must be exactly
+    /// `AUTH_FILE_PATH`.
+    Path,
+    /// The opened file must be the same file (same device and inode numbers)
+    /// as `AUTH_FILE_PATH`, or have the same canonical path if either can't be
+    /// stat'ed.
+    Inode,
+}
+
+/// Whether the auth file is also tracked when it is opened for reading and
+/// writing (e.g., with `fopen` mode `r+`). Set with the `AUTH_FILE_READ_WRITE`
+/// environment variable (or `policy.read-write` in the configuration file), as
+/// `ignore` (the default) or `track`.
+pub static AUTH_FILE_READ_WRITE: Lazy<ReadWritePolicy> =
+    Lazy::new(|| match env::var("AUTH_FILE_READ_WRITE").as_deref() {
+        Err(env::VarError::NotPresent) => SETTINGS.read_write.unwrap_or(ReadWritePolicy::Ignore),
+        Ok("ignore") => ReadWritePolicy::Ignore,
+        Ok("track") => ReadWritePolicy::Track,
+        _ => {
+            error!(
+                "The environment variable AUTH_FILE_READ_WRITE must be either \
+                 'ignore' or 'track'. Using 'ignore'"
+            );
+            ReadWritePolicy::Ignore
+        }
+    });
+
+/// What to do with the auth file when it is opened for reading and writing.
+/// See `AUTH_FILE_READ_WRITE`.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReadWritePolicy {
+    /// Read it as usual, without replacing the password.
+    Ignore,
+    /// Replace the password when reading it, like for read-only opens. Writes
+    /// still go to the file.
+    Track,
+}
+
+/// Where the auth files are. Set with the `AUTH_FILE_STORAGE` environment
+/// variable (or `policy.storage` in the configuration file), as `disk` (the
+/// default) or `memory`.
+pub static AUTH_FILE_STORAGE: Lazy<AuthFileStorage> =
+    Lazy::new(|| match env::var("AUTH_FILE_STORAGE").as_deref() {
+        Err(env::VarError::NotPresent) => SETTINGS.storage.unwrap_or(AuthFileStorage::Disk),
+        Ok("disk") => AuthFileStorage::Disk,
+        Ok("memory") => AuthFileStorage::Memory,
+        _ => {
+            error!(
+                "The environment variable AUTH_FILE_STORAGE must be either \
+                 'disk' or 'memory'. Using 'disk'"
+            );
+            AuthFileStorage::Disk
+        }
+    });
+
+/// Where the auth files are. See `AUTH_FILE_STORAGE`.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthFileStorage {
+    /// They are regular files, which must exist.
+    Disk,
+    /// They don't exist on disk at all. Opening their paths opens in-memory
+    /// files instead (see the `memory_files` module).
+    Memory,
+}
+
+/// Username written in the first line of the in-memory auth files (see
+/// `AUTH_FILE_STORAGE`) whose secret has no username. Set with the
+/// `AUTH_FILE_USERNAME` environment variable (or `policy.username` in the
+/// configuration file).
+pub static AUTH_FILE_USERNAME: Lazy<Option<String>> =
+    Lazy::new(|| optional_var("AUTH_FILE_USERNAME").or_else(|| SETTINGS.username.clone()));
+
+/// What to do with auth files that end before the password line (i.e., that
+/// only contain the username, which OpenVPN accepts, prompting for the
+/// password). Set with the `AUTH_FILE_MISSING_PASSWORD` environment variable
+/// (or `policy.missing-password` in the configuration file), as `ignore` (the
+/// default) or `append`.
+pub static AUTH_FILE_MISSING_PASSWORD: Lazy<MissingPasswordPolicy> =
+    Lazy::new(|| match env::var("AUTH_FILE_MISSING_PASSWORD").as_deref() {
+        Err(env::VarError::NotPresent) => SETTINGS
+            .missing_password
+            .unwrap_or(MissingPasswordPolicy::Ignore),
+        Ok("ignore") => MissingPasswordPolicy::Ignore,
+        Ok("append") => MissingPasswordPolicy::Append,
+        _ => {
+            error!(
+                "The environment variable AUTH_FILE_MISSING_PASSWORD must be \
+                 either 'ignore' or 'append'. Using 'ignore'"
+            );
+            MissingPasswordPolicy::Ignore
+        }
+    });
+
+/// What to do with auth files without a password line. See
+/// `AUTH_FILE_MISSING_PASSWORD`.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissingPasswordPolicy {
+    /// Read them as usual, so that OpenVPN prompts for the password.
+    Ignore,
+    /// Append the password line after the username line, so that no decoy
+    /// password needs to be stored in the file at all.
+    Append,
+}
+
+/// OpenVPN connection password, from the secret named `secret`. It will be
+/// injected when OpenVPN reads an auth file, making it believe the password
+/// was actually written in the second line of the file.
 ///
 /// It is stored in the binary in obfuscated form.
-pub fn password_line() -> Result<CString, Box<dyn Error>> {
-    let nonce = hex::decode(NONCE_HEX)?;
-    let nonce = nonce
-        .try_into()
-        .map_err(|v| format!("Invalid nonce. Must be 12 bytes long. Was: {v:?}"))?;
-    let ciphertext = hex::decode(CIPHERTEXT_HEX)?;
+pub fn password_line(secret: &str) -> Result<CString, Box<dyn Error>> {
+    find_secret(secret)?.password.line()
+}
+
+/// OpenVPN connection username, from the secret named `secret`, if one was
+/// given at compile time. Like the password, it will be injected in place of
+/// the first line of the file.
+pub fn username_line(secret: &str) -> Result<Option<CString>, Box<dyn Error>> {
+    find_secret(secret)?
+        .username
+        .as_ref()
+        .map(EncryptedValue::line)
+        .transpose()
+}
+
+/// Whether the secret named `secret` has a username too. Unknown secrets
+/// don't.
+pub fn has_username(secret: &str) -> bool {
+    find_secret(secret).is_ok_and(|secret| secret.username.is_some())
+}
+
+/// The name of the embedded secret called `name`, if there is one.
+pub fn secret_name(name: &str) -> Option<&'static str> {
+    find_secret(name).ok().map(|secret| secret.name)
+}
+
+/// The secret named `secret`.
+fn find_secret(secret: &str) -> Result<&'static EncryptedSecret, Box<dyn Error>> {
+    Ok(SECRETS
+        .iter()
+        .find(|candidate| candidate.name == secret)
+        .ok_or_else(|| format!("Unknown secret '{secret}'"))?)
+}
 
-    let password = decrypt(&nonce, &ciphertext)?;
-    let password = String::from_utf8(password)?;
+/// Name of the secret given at compile time in `BUILD_ARG_PASSWORD`, which
+/// `AUTH_FILE_PATH` gets unless `secrets.auth-user-pass` in the configuration
+/// file says otherwise.
+const DEFAULT_SECRET: &str = "default";
 
-    Ok(CString::new(format!("{}\n", password).as_bytes())?)
+/// Name of the secret given at compile time in `BUILD_ARG_PROXY_PASSWORD`,
+/// which `PROXY_AUTH_FILE_PATH` gets unless `secrets.proxy` in the
+/// configuration file says otherwise.
+const PROXY_SECRET: &str = "proxy";
+
+/// Name of the secret that the `askpass` file found by `AUTH_FILE_DISCOVERY`
+/// gets (unless `secrets.askpass` in the configuration file says otherwise),
+/// which must be given at compile time as a named secret.
+const ASKPASS_SECRET: &str = "askpass";
+
+/// Name of the (nonexistent) secret of the opaque files, which don't get
+/// any.
+pub const NO_SECRET: &str = "";
+
+/// A secret encrypted at compile time.
+struct EncryptedSecret {
+    /// Name that auth files refer to it by.
+    name: &'static str,
+    /// The password, which replaces the second line of the auth files.
+    password: EncryptedValue,
+    /// The username, which replaces the first line of the auth files, if
+    /// given at compile time.
+    username: Option<EncryptedValue>,
+}
+
+/// A value encrypted at compile time.
+struct EncryptedValue {
+    /// The nonce used to encrypt the value. It is stored in the binary in
+    /// plain text, as it is needed to decrypt the value at runtime, and it is
+    /// not considered a secret.
+    nonce_hex: &'static str,
+    /// The encrypted value, which can be decrypted at runtime using the nonce
+    /// and the encryption key, which is generated from the machine ID at
+    /// runtime.
+    ciphertext_hex: &'static str,
 }
 
-/// The nonce used to encrypt the password. It is provided at compilation time
-/// and stored in the binary in plain text, as it is needed to decrypt the
-/// password as runtime, and it is not considered a secret.
-const NONCE_HEX: &str = env!("BUILD_ARG_NONCE_HEX");
+impl EncryptedValue {
+    /// The decrypted value, as a line of the auth file.
+    fn line(&self) -> Result<CString, Box<dyn Error>> {
+        let nonce = hex::decode(self.nonce_hex)?;
+        let nonce = nonce
+            .try_into()
+            .map_err(|v| format!("Invalid nonce. Must be 12 bytes long. Was: {v:?}"))?;
+        let ciphertext = hex::decode(self.ciphertext_hex)?;
+
+        let value = decrypt(&nonce, &ciphertext)?;
+        let value = String::from_utf8(value)?;
+
+        Ok(CString::new(format!("{}\n", value).as_bytes())?)
+    }
+}
 
-/// The encrypted password. The ciphertext is generated at compile time and
-/// stored in the binary, so that it can be decrypted at runtime, using the
-/// nonce and the encryption key, which is generated from the machine ID at
-/// runtime.
-const CIPHERTEXT_HEX: &str = env!("BUILD_ARG_CIPHERTEXT_HEX");
+// All the secrets, generated by `build.rs` as `SECRETS: &[EncryptedSecret]`
+include!(concat!(env!("OUT_DIR"), "/secrets.rs"));
@@ -0,0 +1,253 @@
+//! Replacements for the stdio file positioning functions.
+//!
+//! Once the virtual content of a tracked stream has been loaded, the underlying
+//! stream is left at EOF, and the position seen by the caller is the one in the
+//! virtual content instead. Also, the stream position is tracked (see the
+//! `state` module) to know when `fgets` reaches the password line. So for
+//! tracked streams, these replacements keep both in sync with what the caller
+//! expects. Untracked streams are handled by the original functions, as usual.
+//!
+//! Repositioning a tracked stream always starts over from the beginning of the
+//! file: the underlying stream is rewound and its tracking is reset. If the
+//! target position is not the beginning of the file, the virtual content is
+//! then loaded (from the beginning) and read up to that position. That way a
+//! file can be read as many times as needed and the password is always
+//! replaced.
+
+use libc::{
+    c_int, c_long, fpos64_t, fpos_t, off64_t, off_t, EOF, FILE, SEEK_CUR, SEEK_END, SEEK_SET,
+};
+
+use crate::{functions::Functions, guard, state::State};
+
+/// Replacement for the `rewind` libc function.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn rewind(stream: *mut FILE) {
+    Functions::rewind(stream);
+    if State::is_tracking() {
+        guard::catch_panic("rewind", || State::reset(stream));
+    }
+}
+
+/// Replacement for the `fseek` libc function.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn fseek(stream: *mut FILE, offset: c_long, whence: c_int) -> c_int {
+    guard::or_original(
+        "fseek",
+        || seek(stream, offset as off64_t, whence),
+        || Functions::fseek(stream, offset, whence),
+    )
+}
+
+/// Replacement for the `fseeko` libc function.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn fseeko(stream: *mut FILE, offset: off_t, whence: c_int) -> c_int {
+    guard::or_original(
+        "fseeko",
+        || seek(stream, offset, whence),
+        || Functions::fseeko(stream, offset, whence),
+    )
+}
+
+/// Replacement for the `fseeko64` libc function, the large file variant of
+/// `fseeko`.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn fseeko64(stream: *mut FILE, offset: off64_t, whence: c_int) -> c_int {
+    guard::or_original(
+        "fseeko64",
+        || seek(stream, offset, whence),
+        || Functions::fseeko64(stream, offset, whence),
+    )
+}
+
+/// Replacement for the `fsetpos` libc function.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed. `pos` must be a
+/// valid pointer to a position obtained with `fgetpos` on the same stream.
+#[no_mangle]
+pub unsafe extern "C" fn fsetpos(stream: *mut FILE, pos: *const fpos_t) -> c_int {
+    guard::or_original(
+        "fsetpos",
+        || seek(stream, *pos.cast::<off_t>(), SEEK_SET),
+        || Functions::fsetpos(stream, pos),
+    )
+}
+
+/// Replacement for the `fsetpos64` libc function, the large file variant of
+/// `fsetpos`.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed. `pos` must be a
+/// valid pointer to a position obtained with `fgetpos64` on the same stream.
+#[no_mangle]
+pub unsafe extern "C" fn fsetpos64(stream: *mut FILE, pos: *const fpos64_t) -> c_int {
+    guard::or_original(
+        "fsetpos64",
+        || seek(stream, *pos.cast::<off64_t>(), SEEK_SET),
+        || Functions::fsetpos64(stream, pos),
+    )
+}
+
+/// Replacement for the `ftell` libc function.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn ftell(stream: *mut FILE) -> c_long {
+    guard::or_original(
+        "ftell",
+        || State::position(stream).map(|position| position as c_long),
+        || Functions::ftell(stream),
+    )
+}
+
+/// Replacement for the `ftello` libc function.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn ftello(stream: *mut FILE) -> off_t {
+    guard::or_original(
+        "ftello",
+        || State::position(stream).map(|position| position as off_t),
+        || Functions::ftello(stream),
+    )
+}
+
+/// Replacement for the `ftello64` libc function, the large file variant of
+/// `ftello`.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn ftello64(stream: *mut FILE) -> off64_t {
+    guard::or_original(
+        "ftello64",
+        || State::position(stream).map(|position| position as off64_t),
+        || Functions::ftello64(stream),
+    )
+}
+
+/// Replacement for the `fgetpos` libc function.
+///
+/// The original function fills in `pos`, and then the position in it is
+/// replaced for tracked streams. This relies on glibc's `fpos_t` layout, which
+/// starts with the position as an `off_t`.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed. `pos` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn fgetpos(stream: *mut FILE, pos: *mut fpos_t) -> c_int {
+    let result = Functions::fgetpos(stream, pos);
+    if result == 0 && State::is_tracking() {
+        guard::catch_panic("fgetpos", || {
+            if let Some(position) = State::position(stream) {
+                *pos.cast::<off_t>() = position as off_t;
+            }
+        });
+    }
+    result
+}
+
+/// Replacement for the `fgetpos64` libc function, the large file variant of
+/// `fgetpos`. See `fgetpos`.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed. `pos` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn fgetpos64(stream: *mut FILE, pos: *mut fpos64_t) -> c_int {
+    let result = Functions::fgetpos64(stream, pos);
+    if result == 0 && State::is_tracking() {
+        guard::catch_panic("fgetpos64", || {
+            if let Some(position) = State::position(stream) {
+                *pos.cast::<off64_t>() = position as off64_t;
+            }
+        });
+    }
+    result
+}
+
+/// Replacement for the `ungetc` libc function.
+///
+/// For tracked streams, the byte is pushed back into the virtual content
+/// (which is loaded first, if needed).
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn ungetc(c: c_int, stream: *mut FILE) -> c_int {
+    if c == EOF {
+        return Functions::ungetc(c, stream);
+    }
+    let byte = c as u8;
+    guard::or_original(
+        "ungetc",
+        || {
+            State::with_content(stream, |content| content.unread(byte))?;
+            // A successful `ungetc` clears the EOF indicator
+            libc::clearerr(stream);
+            Some(byte.into())
+        },
+        || Functions::ungetc(c, stream),
+    )
+}
+
+/// Common logic for the `fseek` family of replacements. Returns `None` if the
+/// stream is not tracked.
+unsafe fn seek(stream: *mut FILE, offset: off64_t, whence: c_int) -> Option<c_int> {
+    let current = State::position(stream)?;
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => current,
+        SEEK_END => current + State::with_content(stream, |content| content.remaining().len())?,
+        _ => return Some(invalid_argument()),
+    };
+    let Some(target) = (base as off64_t)
+        .checked_add(offset)
+        .and_then(|target| usize::try_from(target).ok())
+    else {
+        return Some(invalid_argument());
+    };
+
+    if Functions::fseeko64(stream, 0, SEEK_SET) != 0 {
+        return Some(-1);
+    }
+    State::reset(stream);
+    if target > 0 {
+        State::with_content(stream, |content| content.seek(target));
+    }
+    Some(0)
+}
+
+/// Set `errno` to `EINVAL` and return the error value of the `fseek` family.
+unsafe fn invalid_argument() -> c_int {
+    *libc::__errno_location() = libc::EINVAL;
+    -1
+}
@@ -0,0 +1,495 @@
+//! Replacements for the stdio reader functions other than `fgets`.
+//!
+//! OpenVPN reads the `auth-user-pass` file with `fgets`, but other versions
+//! and other clients may use any other stdio function to read it. For tracked
+//! streams, all these replacements serve the virtual content of the stream
+//! (see the `content` module) instead of reading from the file. Untracked
+//! streams are read with the original functions, as usual.
+//!
+//! The `_unlocked` variants behave exactly like the regular ones, as the
+//! virtual content is protected by the global state lock anyway.
+
+use std::{ptr, slice};
+
+use libc::{c_char, c_int, c_void, size_t, ssize_t, EOF, FILE};
+
+use crate::{
+    content::{self, Content},
+    functions::{Functions, VaList},
+    guard,
+    state::State,
+};
+
+/// Body of a naked function that jumps to `$target`.
+macro_rules! tail_call {
+    ($target:ident) => {{
+        #[cfg(target_arch = "x86_64")]
+        core::arch::naked_asm!("jmp {}", sym $target);
+        #[cfg(target_arch = "aarch64")]
+        core::arch::naked_asm!("b {}", sym $target);
+    }};
+}
+
+/// Replacement for the `getline` libc function.
+///
+/// # Safety
+///
+/// Same requirements as `getline`.
+#[no_mangle]
+pub unsafe extern "C" fn getline(
+    lineptr: *mut *mut c_char,
+    n: *mut size_t,
+    stream: *mut FILE,
+) -> ssize_t {
+    guard::or_original(
+        "getline",
+        || read_delimited(lineptr, n, b'\n', stream),
+        || Functions::getline(lineptr, n, stream),
+    )
+}
+
+/// Replacement for the `getdelim` libc function.
+///
+/// # Safety
+///
+/// Same requirements as `getdelim`.
+#[no_mangle]
+pub unsafe extern "C" fn getdelim(
+    lineptr: *mut *mut c_char,
+    n: *mut size_t,
+    delim: c_int,
+    stream: *mut FILE,
+) -> ssize_t {
+    guard::or_original(
+        "getdelim",
+        || read_delimited(lineptr, n, delim as u8, stream),
+        || Functions::getdelim(lineptr, n, delim, stream),
+    )
+}
+
+/// Replacement for the `__getdelim` glibc function, an alias of `getdelim`.
+///
+/// # Safety
+///
+/// Same requirements as `getdelim`.
+#[no_mangle]
+pub unsafe extern "C" fn __getdelim(
+    lineptr: *mut *mut c_char,
+    n: *mut size_t,
+    delim: c_int,
+    stream: *mut FILE,
+) -> ssize_t {
+    guard::or_original(
+        "__getdelim",
+        || read_delimited(lineptr, n, delim as u8, stream),
+        || Functions::getdelim_internal(lineptr, n, delim, stream),
+    )
+}
+
+/// Replacement for the `fgetc` libc function.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn fgetc(stream: *mut FILE) -> c_int {
+    guard::or_original(
+        "fgetc",
+        || read_byte(stream, Content::read_byte),
+        || Functions::fgetc(stream),
+    )
+}
+
+/// Replacement for the `getc` libc function.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn getc(stream: *mut FILE) -> c_int {
+    guard::or_original(
+        "getc",
+        || read_byte(stream, Content::read_byte),
+        || Functions::getc(stream),
+    )
+}
+
+/// Replacement for the `_IO_getc` glibc function, an alias of `getc`.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn _IO_getc(stream: *mut FILE) -> c_int {
+    guard::or_original(
+        "_IO_getc",
+        || read_byte(stream, Content::read_byte),
+        || Functions::io_getc(stream),
+    )
+}
+
+/// Replacement for the `fgetc_unlocked` glibc function.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn fgetc_unlocked(stream: *mut FILE) -> c_int {
+    guard::or_original(
+        "fgetc_unlocked",
+        || read_byte(stream, Content::read_byte),
+        || Functions::fgetc_unlocked(stream),
+    )
+}
+
+/// Replacement for the `getc_unlocked` libc function.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn getc_unlocked(stream: *mut FILE) -> c_int {
+    guard::or_original(
+        "getc_unlocked",
+        || read_byte(stream, Content::read_byte),
+        || Functions::getc_unlocked(stream),
+    )
+}
+
+/// Replacement for the `__uflow` glibc function.
+///
+/// When optimizations are enabled, glibc inlines `getc_unlocked` and friends,
+/// which then read straight from the stream buffer and only call `__uflow`
+/// when the buffer is empty. The buffer of a tracked stream is always empty
+/// once its virtual content is loaded, so every read goes through here.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn __uflow(stream: *mut FILE) -> c_int {
+    guard::or_original(
+        "__uflow",
+        || read_byte(stream, Content::read_byte),
+        || Functions::uflow(stream),
+    )
+}
+
+/// Replacement for the `__underflow` glibc function.
+///
+/// Like `__uflow`, but without consuming the byte.
+///
+/// # Safety
+///
+/// `stream` must be a valid pointer to a FILE, not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn __underflow(stream: *mut FILE) -> c_int {
+    guard::or_original(
+        "__underflow",
+        || read_byte(stream, |content| content.peek_byte()),
+        || Functions::underflow(stream),
+    )
+}
+
+/// Replacement for the `fread` libc function.
+///
+/// # Safety
+///
+/// Same requirements as `fread`.
+#[no_mangle]
+pub unsafe extern "C" fn fread(
+    ptr: *mut c_void,
+    size: size_t,
+    nmemb: size_t,
+    stream: *mut FILE,
+) -> size_t {
+    guard::or_original(
+        "fread",
+        || read_items(ptr, size, nmemb, stream),
+        || Functions::fread(ptr, size, nmemb, stream),
+    )
+}
+
+/// Replacement for the `fread_unlocked` glibc function.
+///
+/// # Safety
+///
+/// Same requirements as `fread`.
+#[no_mangle]
+pub unsafe extern "C" fn fread_unlocked(
+    ptr: *mut c_void,
+    size: size_t,
+    nmemb: size_t,
+    stream: *mut FILE,
+) -> size_t {
+    guard::or_original(
+        "fread_unlocked",
+        || read_items(ptr, size, nmemb, stream),
+        || Functions::fread_unlocked(ptr, size, nmemb, stream),
+    )
+}
+
+/// Replacement for the `__fread_chk` glibc function, the `-D_FORTIFY_SOURCE`
+/// variant of `fread`.
+///
+/// # Safety
+///
+/// Same requirements as `fread`. `ptr` must be valid for writes of `ptrlen`
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn __fread_chk(
+    ptr: *mut c_void,
+    ptrlen: size_t,
+    size: size_t,
+    nmemb: size_t,
+    stream: *mut FILE,
+) -> size_t {
+    if !fits(ptrlen, size, nmemb) {
+        // Let the original function report the buffer overflow
+        return Functions::fread_chk(ptr, ptrlen, size, nmemb, stream);
+    }
+    guard::or_original(
+        "__fread_chk",
+        || read_items(ptr, size, nmemb, stream),
+        || Functions::fread_chk(ptr, ptrlen, size, nmemb, stream),
+    )
+}
+
+/// Replacement for the `__fread_unlocked_chk` glibc function, the
+/// `-D_FORTIFY_SOURCE` variant of `fread_unlocked`.
+///
+/// # Safety
+///
+/// Same requirements as `fread`. `ptr` must be valid for writes of `ptrlen`
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn __fread_unlocked_chk(
+    ptr: *mut c_void,
+    ptrlen: size_t,
+    size: size_t,
+    nmemb: size_t,
+    stream: *mut FILE,
+) -> size_t {
+    if !fits(ptrlen, size, nmemb) {
+        // Let the original function report the buffer overflow
+        return Functions::fread_unlocked_chk(ptr, ptrlen, size, nmemb, stream);
+    }
+    guard::or_original(
+        "__fread_unlocked_chk",
+        || read_items(ptr, size, nmemb, stream),
+        || Functions::fread_unlocked_chk(ptr, ptrlen, size, nmemb, stream),
+    )
+}
+
+/// Replacement for the `vfscanf` libc function.
+///
+/// It is also what our `fscanf` replacement ends up calling (see
+/// `variadic.c`).
+///
+/// # Safety
+///
+/// Same requirements as `vfscanf`.
+#[no_mangle]
+pub unsafe extern "C" fn vfscanf(stream: *mut FILE, format: *const c_char, ap: VaList) -> c_int {
+    guard::or_original(
+        "vfscanf",
+        || scan(stream, format, ap, Functions::vfscanf),
+        || Functions::vfscanf(stream, format, ap),
+    )
+}
+
+/// Replacement for the `__isoc99_vfscanf` glibc function, which is what
+/// programs compiled in C99 (or later) mode call instead of `vfscanf`.
+///
+/// # Safety
+///
+/// Same requirements as `vfscanf`.
+#[no_mangle]
+pub unsafe extern "C" fn __isoc99_vfscanf(
+    stream: *mut FILE,
+    format: *const c_char,
+    ap: VaList,
+) -> c_int {
+    guard::or_original(
+        "__isoc99_vfscanf",
+        || scan(stream, format, ap, Functions::isoc99_vfscanf),
+        || Functions::isoc99_vfscanf(stream, format, ap),
+    )
+}
+
+/// Replacement for the `__isoc23_vfscanf` glibc function, which is what
+/// programs compiled in C23 mode call instead of `vfscanf` (since glibc 2.38).
+/// Older versions of glibc don't have it, but programs calling it can't run
+/// with them anyway.
+///
+/// # Safety
+///
+/// Same requirements as `vfscanf`.
+#[no_mangle]
+pub unsafe extern "C" fn __isoc23_vfscanf(
+    stream: *mut FILE,
+    format: *const c_char,
+    ap: VaList,
+) -> c_int {
+    guard::or_original(
+        "__isoc23_vfscanf",
+        || scan(stream, format, ap, Functions::isoc23_vfscanf),
+        || Functions::isoc23_vfscanf(stream, format, ap),
+    )
+}
+
+/// Replacement for the variadic `fscanf` libc function.
+///
+/// Variadic functions can't be defined in stable Rust, so this just jumps to
+/// the C implementation in `variadic.c`, which forwards the variable arguments
+/// to our `vfscanf` replacement. The jump leaves registers and stack untouched,
+/// so the C function receives the arguments exactly as passed by the caller.
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn fscanf() {
+    tail_call!(openvpn_auth_hook_fscanf)
+}
+
+/// Replacement for the variadic `__isoc99_fscanf` glibc function, which is
+/// what programs compiled in C99 (or later) mode call instead of `fscanf`. See
+/// `fscanf`.
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn __isoc99_fscanf() {
+    tail_call!(openvpn_auth_hook_isoc99_fscanf)
+}
+
+/// Replacement for the variadic `__isoc23_fscanf` glibc function, which is
+/// what programs compiled in C23 mode call instead of `fscanf` (since glibc
+/// 2.38). See `fscanf`.
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn __isoc23_fscanf() {
+    tail_call!(openvpn_auth_hook_isoc23_fscanf)
+}
+
+extern "C" {
+    fn openvpn_auth_hook_fscanf();
+    fn openvpn_auth_hook_isoc99_fscanf();
+    fn openvpn_auth_hook_isoc23_fscanf();
+}
+
+/// Common logic for the `getdelim` family of replacements. Returns `None` if
+/// the stream is not tracked.
+unsafe fn read_delimited(
+    lineptr: *mut *mut c_char,
+    n: *mut size_t,
+    delim: u8,
+    stream: *mut FILE,
+) -> Option<ssize_t> {
+    /// Minimum size of the buffer allocated for the line (same as glibc).
+    const MIN_BUFFER_SIZE: usize = 120;
+
+    if lineptr.is_null() || n.is_null() {
+        // Let the original function report the error
+        return None;
+    }
+    let line = State::with_content(stream, |content| {
+        content.read_until(delim, usize::MAX).to_vec()
+    })?;
+    if line.last() != Some(&delim) {
+        content::set_eof_indicator(stream);
+    }
+    if line.is_empty() {
+        return Some(-1);
+    }
+    let required_size = line.len() + 1;
+    if (*lineptr).is_null() || *n < required_size {
+        let new_size = required_size.max(MIN_BUFFER_SIZE);
+        let new_buffer = libc::realloc((*lineptr).cast(), new_size);
+        if new_buffer.is_null() {
+            *libc::__errno_location() = libc::ENOMEM;
+            return Some(-1);
+        }
+        *lineptr = new_buffer.cast();
+        *n = new_size;
+    }
+    ptr::copy_nonoverlapping(line.as_ptr(), (*lineptr).cast(), line.len());
+    *(*lineptr).add(line.len()) = 0;
+    Some(line.len() as ssize_t)
+}
+
+/// Common logic for the `getc` family of replacements. `read` obtains the byte
+/// from the virtual content. Returns `None` if the stream is not tracked.
+unsafe fn read_byte(
+    stream: *mut FILE,
+    read: impl FnOnce(&mut Content) -> Option<u8>,
+) -> Option<c_int> {
+    match State::with_content(stream, read)? {
+        Some(byte) => Some(byte.into()),
+        None => {
+            content::set_eof_indicator(stream);
+            Some(EOF)
+        }
+    }
+}
+
+/// Common logic for the `fread` family of replacements. Returns `None` if the
+/// stream is not tracked, or if the size of the items overflows (which is left
+/// to the original function).
+unsafe fn read_items(
+    ptr: *mut c_void,
+    size: size_t,
+    nmemb: size_t,
+    stream: *mut FILE,
+) -> Option<size_t> {
+    let requested = size.checked_mul(nmemb)?;
+    let read = State::with_content(stream, |content| {
+        if requested == 0 {
+            return 0;
+        }
+        content.read(slice::from_raw_parts_mut(ptr.cast(), requested))
+    })?;
+    if read < requested {
+        content::set_eof_indicator(stream);
+    }
+    Some(read.checked_div(size).unwrap_or(0))
+}
+
+/// Whether `nmemb` items of `size` bytes fit in a buffer of `ptrlen` bytes.
+fn fits(ptrlen: size_t, size: size_t, nmemb: size_t) -> bool {
+    size.checked_mul(nmemb).is_some_and(|len| len <= ptrlen)
+}
+
+/// Common logic for the `vfscanf` family of replacements. Returns `None` if
+/// the stream is not tracked.
+///
+/// The virtual content is scanned with the `original` function, through a
+/// temporary memory stream over the remaining content. Then, as much content
+/// as was consumed from the memory stream is consumed from the virtual content.
+unsafe fn scan(
+    stream: *mut FILE,
+    format: *const c_char,
+    ap: VaList,
+    original: fn(*mut FILE, *const c_char, VaList) -> c_int,
+) -> Option<c_int> {
+    let (result, hit_eof) = State::with_content(stream, |content| {
+        let remaining = content.remaining();
+        if remaining.is_empty() {
+            return (EOF, true);
+        }
+        let memory_stream = libc::fmemopen(
+            remaining.as_ptr() as *mut c_void,
+            remaining.len(),
+            c"r".as_ptr(),
+        );
+        if memory_stream.is_null() {
+            return (EOF, false);
+        }
+        let result = original(memory_stream, format, ap);
+        let consumed = Functions::ftell(memory_stream);
+        let hit_eof = libc::feof(memory_stream) != 0;
+        Functions::fclose(memory_stream);
+        content.consume(consumed.try_into().unwrap_or(0));
+        (result, hit_eof)
+    })?;
+    if hit_eof {
+        content::set_eof_indicator(stream);
+    }
+    Some(result)
+}
@@ -0,0 +1,274 @@
+//! The configuration file of the hook, in TOML, as an alternative to the
+//! environment variables (see `params`). It is read from the path in the
+//! `AUTH_HOOK_CONFIG` environment variable or, if not set, from
+//! `/etc/openvpn-auth-hook/config.toml` (if it exists).
+//!
+//! ```toml
+//! discovery = "openvpn"          # AUTH_FILE_DISCOVERY
+//! matching = "inode"             # AUTH_FILE_MATCHING
+//!
+//! [[files]]                      # Like the entries of AUTH_FILES
+//! path = "/etc/openvpn/client/office.txt"
+//! secret = "office"
+//! kind = "auth-user-pass"        # The default
+//!
+//! [[files]]                      # Like the entries of ENCRYPTED_FILES
+//! path = "/etc/openvpn/client/client.key.sealed"
+//! encrypted = true
+//!
+//! [secrets]                      # The secrets that each kind of file gets
+//! auth-user-pass = "default"     # AUTH_FILE_PATH and the discovered one
+//! askpass = "askpass"            # The discovered askpass file
+//! proxy = "proxy"                # PROXY_AUTH_FILE_PATH and the discovered one
+//!
+//! [policy]
+//! read-write = "ignore"          # AUTH_FILE_READ_WRITE
+//! storage = "disk"               # AUTH_FILE_STORAGE
+//! username = "alice"             # AUTH_FILE_USERNAME
+//! missing-password = "ignore"    # AUTH_FILE_MISSING_PASSWORD
+//!
+//! [logging]
+//! level = "warning"              # Or "error", or "off"
+//! ```
+//!
+//! Every key is optional. The environment variables override the keys they
+//! correspond to, and the files given in environment variables take
+//! precedence over the `files` with the same path.
+//!
+//! The whole file is validated once, when loaded. If it can't be parsed, or
+//! has unknown keys or values, it is ignored altogether. Files and secrets
+//! naming unknown secrets are ignored on their own. Either way, the error is
+//! reported along with the line of the file.
+
+use std::{env, fs, ops::Range};
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::{
+    log::LogLevel,
+    params::{
+        self, AuthFile, AuthFileDiscovery, AuthFileKind, AuthFileMatching, AuthFileStorage,
+        MissingPasswordPolicy, ReadWritePolicy,
+    },
+};
+
+/// Where the configuration file is, unless `AUTH_HOOK_CONFIG` says otherwise.
+const DEFAULT_PATH: &str = "/etc/openvpn-auth-hook/config.toml";
+
+/// The settings of the configuration file, once validated. All of them are
+/// unset if there is no (valid) configuration file.
+pub static SETTINGS: Lazy<Settings> = Lazy::new(load);
+
+/// The settings of the configuration file. See the module documentation for
+/// the keys they come from.
+#[derive(Default)]
+pub struct Settings {
+    pub discovery: Option<AuthFileDiscovery>,
+    pub matching: Option<AuthFileMatching>,
+    pub files: Vec<AuthFile>,
+    pub auth_user_pass_secret: Option<&'static str>,
+    pub askpass_secret: Option<&'static str>,
+    pub proxy_secret: Option<&'static str>,
+    pub read_write: Option<ReadWritePolicy>,
+    pub storage: Option<AuthFileStorage>,
+    pub username: Option<String>,
+    pub missing_password: Option<MissingPasswordPolicy>,
+    pub log_level: LogLevel,
+}
+
+/// The configuration file, as written.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    discovery: Option<AuthFileDiscovery>,
+    matching: Option<AuthFileMatching>,
+    files: Vec<Spanned<FileEntry>>,
+    secrets: SecretsTable,
+    policy: PolicyTable,
+    logging: LoggingTable,
+}
+
+/// An entry of `files`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct FileEntry {
+    path: String,
+    kind: Option<AuthFileKind>,
+    secret: Option<Spanned<String>>,
+    #[serde(default)]
+    encrypted: bool,
+}
+
+/// The `[secrets]` table.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct SecretsTable {
+    auth_user_pass: Option<Spanned<String>>,
+    askpass: Option<Spanned<String>>,
+    proxy: Option<Spanned<String>>,
+}
+
+/// The `[policy]` table.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct PolicyTable {
+    read_write: Option<ReadWritePolicy>,
+    storage: Option<AuthFileStorage>,
+    username: Option<String>,
+    missing_password: Option<MissingPasswordPolicy>,
+}
+
+/// The `[logging]` table.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct LoggingTable {
+    level: LogLevel,
+}
+
+/// Read and validate the configuration file, reporting any problem.
+fn load() -> Settings {
+    let path = match env::var("AUTH_HOOK_CONFIG") {
+        Ok(path) => path,
+        Err(env::VarError::NotPresent) if fs::metadata(DEFAULT_PATH).is_ok() => {
+            DEFAULT_PATH.to_owned()
+        }
+        Err(env::VarError::NotPresent) => return Settings::default(),
+        Err(env::VarError::NotUnicode(_)) => {
+            error!("The environment variable AUTH_HOOK_CONFIG is not a valid UTF-8 string");
+            return Settings::default();
+        }
+    };
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            error!("Unable to read the configuration file '{path}': {err}");
+            return Settings::default();
+        }
+    };
+    let file: ConfigFile = match toml::from_str(&contents) {
+        Ok(file) => file,
+        Err(err) => {
+            let location = err.span().map_or_else(String::new, |span| {
+                format!(" at line {}", line_number(&contents, &span))
+            });
+            error!(
+                "Invalid configuration file '{path}'{location}: {}. Ignoring it",
+                err.message()
+            );
+            return Settings::default();
+        }
+    };
+    let mut validator = Validator {
+        path: &path,
+        contents: &contents,
+        errors: Vec::new(),
+    };
+    let settings = Settings {
+        discovery: file.discovery,
+        matching: file.matching,
+        files: file
+            .files
+            .into_iter()
+            .filter_map(|entry| validator.file(entry))
+            .collect(),
+        auth_user_pass_secret: validator.secret(file.secrets.auth_user_pass),
+        askpass_secret: validator.secret(file.secrets.askpass),
+        proxy_secret: validator.secret(file.secrets.proxy),
+        read_write: file.policy.read_write,
+        storage: file.policy.storage,
+        username: file.policy.username,
+        missing_password: file.policy.missing_password,
+        log_level: file.logging.level,
+    };
+    // Still loading the settings, so `error!` doesn't know the log level yet
+    if LogLevel::Error.is_shown_at(settings.log_level) {
+        for error in validator.errors {
+            error!("{error}");
+        }
+    }
+    settings
+}
+
+/// Validation of the parts of the configuration file that refer to the
+/// embedded secrets.
+struct Validator<'a> {
+    /// Path of the configuration file.
+    path: &'a str,
+    /// Contents of the configuration file.
+    contents: &'a str,
+    /// The problems found so far.
+    errors: Vec<String>,
+}
+
+impl Validator<'_> {
+    /// The auth file of a `files` entry, if valid. Only encrypted files can
+    /// go without a secret, as long as they don't have a kind either.
+    fn file(&mut self, entry: Spanned<FileEntry>) -> Option<AuthFile> {
+        let span = entry.span();
+        let entry = entry.into_inner();
+        let (secret, kind) = match (entry.secret, entry.kind) {
+            (None, None) if entry.encrypted => (params::NO_SECRET, AuthFileKind::Opaque),
+            (None, _) => {
+                self.error(
+                    &span,
+                    "Missing secret",
+                    &format!("Ignoring '{}'", entry.path),
+                );
+                return None;
+            }
+            (Some(secret), kind) => {
+                let Some(name) = params::secret_name(secret.get_ref()) else {
+                    self.error(
+                        &secret.span(),
+                        &format!("Unknown secret '{}'", secret.get_ref()),
+                        &format!("Ignoring '{}'", entry.path),
+                    );
+                    return None;
+                };
+                (name, kind.unwrap_or(AuthFileKind::UserPass))
+            }
+        };
+        Some(AuthFile {
+            path: entry.path,
+            secret,
+            kind,
+            encrypted: entry.encrypted,
+        })
+    }
+
+    /// The name of the embedded secret given in `[secrets]`, if any and
+    /// known.
+    fn secret(&mut self, secret: Option<Spanned<String>>) -> Option<&'static str> {
+        let secret = secret?;
+        let name = params::secret_name(secret.get_ref());
+        if name.is_none() {
+            self.error(
+                &secret.span(),
+                &format!("Unknown secret '{}'", secret.get_ref()),
+                "Ignoring it",
+            );
+        }
+        name
+    }
+
+    /// Record the `problem` found at `span` of the file, and what is done
+    /// about it (`consequence`).
+    fn error(&mut self, span: &Range<usize>, problem: &str, consequence: &str) {
+        self.errors.push(format!(
+            "{problem} in the configuration file '{}' at line {}. {consequence}",
+            self.path,
+            line_number(self.contents, span)
+        ));
+    }
+}
+
+/// Number of the line of `contents` where `span` starts, from 1.
+fn line_number(contents: &str, span: &Range<usize>) -> usize {
+    contents.as_bytes()[..span.start.min(contents.len())]
+        .iter()
+        .filter(|&&byte| byte == b'\n')
+        .count()
+        + 1
+}
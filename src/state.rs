@@ -1,61 +1,422 @@
 //! Global state managed by the hook.
 //!
 //! The hook needs to keep track of what `auth-user-pass` files (streams) are
-//! open and how many lines have been read from them. In practice we don't
-//! expect that there will be more than one stream open at a time, but we still
-//! support it.
+//! open, which auth file each of them is (and so, which secret it gets), and
+//! how far they have been read (in bytes and in lines), or their virtual
+//! content once it has been loaded (see the `content` module).
+//!
+//! Files opened with `open` (instead of `fopen`) are tracked by file
+//! descriptor in the same way. The file descriptor underlying each tracked
+//! stream is tracked too, as some clients (e.g., C++'s `std::ifstream`) open
+//! the file with `fopen` but read it with `read`.
+//!
+//! Every stream and file descriptor in the process goes through the hook, but
+//! almost none of them are the `auth-user-pass` file, which is usually not
+//! even open. So the number of tracked entries is also kept in an atomic, and
+//! the global state is not locked at all while it's zero: a single atomic load
+//! tells that no stream or file descriptor is tracked.
 
-use libc::FILE;
+use libc::{c_int, FILE};
 use once_cell::sync::Lazy;
-use std::{collections::HashMap, sync::Mutex};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+};
+
+use crate::{
+    content::Content,
+    guard,
+    matching::{self, FileIdentity},
+    params::AuthFile,
+};
 
-static OPEN_FILES: Lazy<Mutex<HashMap<usize, StreamState>>> = Lazy::new(Default::default);
+static OPEN_FILES: Lazy<Mutex<OpenFiles>> = Lazy::new(|| {
+    register_fork_handlers();
+    Default::default()
+});
+
+/// Number of streams and file descriptors in `OPEN_FILES`. Only updated with
+/// the lock held, but read without it.
+static TRACKED_ENTRIES: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Lock on the global state, held by the thread that is calling `fork`
+    /// for the duration of the call. See `register_fork_handlers`.
+    static FORK_GUARD: RefCell<Option<MutexGuard<'static, OpenFiles>>> =
+        const { RefCell::new(None) };
+}
 
 /// Namespace for functions that manipulate the global state.
 pub struct State;
 
 impl State {
+    /// Whether any stream or file descriptor is being tracked. When not, no
+    /// other function of the global state needs to be called.
+    pub fn is_tracking() -> bool {
+        TRACKED_ENTRIES.load(Ordering::Acquire) != 0
+    }
+
     /// Add a new stream to the global state. Tracks that a new stream has been
-    /// opened with `fopen`.
-    pub fn add(stream: *mut FILE) {
-        let previous_value = OPEN_FILES
-            .lock()
-            .unwrap()
-            .insert(stream as usize, StreamState::new());
+    /// opened with `fopen` on `auth_file`.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be a valid pointer to a FILE, not yet closed.
+    pub unsafe fn add(stream: *mut FILE, auth_file: &'static AuthFile) {
+        let fd = libc::fileno(stream);
+        let identity = matching::descriptor_identity(fd);
+        let mut open_files = lock();
+        let previous_value = open_files
+            .streams
+            .insert(stream as usize, StreamState::new(fd, identity, auth_file));
         if previous_value.is_some() {
-            eprintln!("[Hook] WARNING: Stream {:p} was already in the map", stream);
+            warning!("Stream {:p} was already in the map", stream);
+        }
+        if fd != -1 {
+            open_files
+                .descriptors
+                .insert(fd, DescriptorState::new(identity, auth_file));
         }
+        open_files.count_entries();
     }
 
-    /// Increment the number of lines read from a stream. Tracks that a new line
-    /// has been read from the stream via `fgets`. Returns the number of lines
-    /// read so far from the stream.
-    pub fn inc_lines(stream: *mut FILE) -> Option<usize> {
-        OPEN_FILES
-            .lock()
-            .unwrap()
-            .get_mut(&(stream as usize))
-            .map(|state| state.inc())
+    /// Advance the read position of a stream past `bytes`. Tracks that `bytes`
+    /// have been read from the stream via `fgets`, which may be a whole line or
+    /// just a chunk of it.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be a valid pointer to a FILE, not yet closed.
+    pub unsafe fn advance(stream: *mut FILE, bytes: &[u8]) {
+        let Some(mut open_files) = lock_tracked() else {
+            return;
+        };
+        if let Some(state) = open_files.stream(stream) {
+            state.advance(bytes);
+        }
+    }
+
+    /// Whether the next read from a stream must be served from its virtual
+    /// content. That is the case once the content has been loaded, or when the
+    /// next byte to be read is the start of the first replaced line (see
+    /// `AuthFile::first_replaced_line`).
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be a valid pointer to a FILE, not yet closed.
+    pub unsafe fn reached_replaced_line(stream: *mut FILE) -> bool {
+        let Some(mut open_files) = lock_tracked() else {
+            return false;
+        };
+        open_files.stream(stream).is_some_and(|state| {
+            state.content.is_some()
+                || (state.is_at_line_start()
+                    && state.lines + 1 == state.auth_file.first_replaced_line())
+        })
+    }
+
+    /// Run `f` on the virtual content of a stream, loading it first if it
+    /// hasn't been loaded yet. Returns `None` (without running `f`) if the
+    /// stream is not being tracked.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be a valid pointer to a FILE, not yet closed.
+    pub unsafe fn with_content<R>(
+        stream: *mut FILE,
+        f: impl FnOnce(&mut Content) -> R,
+    ) -> Option<R> {
+        let (lines, mid_line, auth_file) = {
+            let mut open_files = lock_tracked()?;
+            let state = open_files.stream(stream)?;
+            if let Some(content) = state.content.as_mut() {
+                return Some(f(content));
+            }
+            (state.lines, !state.is_at_line_start(), state.auth_file)
+        };
+        // Loaded without holding the lock, as loading may end up calling our
+        // own hooks (e.g., to read the machine-id to decrypt the password)
+        let content = Content::load(stream, lines, mid_line, auth_file);
+        let mut open_files = lock_tracked()?;
+        let state = open_files.stream(stream)?;
+        Some(f(state.content.get_or_insert(content)))
+    }
+
+    /// Current position of a stream, in bytes from the beginning of the file as
+    /// seen by the caller (i.e., with the password line already replaced).
+    /// Returns `None` if the stream is not being tracked.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be a valid pointer to a FILE, not yet closed.
+    pub unsafe fn position(stream: *mut FILE) -> Option<usize> {
+        lock_tracked()?.stream(stream).map(|state| {
+            state.offset
+                + state
+                    .content
+                    .as_ref()
+                    .map_or(0, |content| content.position())
+        })
+    }
+
+    /// Reset a stream to the beginning of the file, discarding its virtual
+    /// content. Tracks that the underlying stream has been rewound. Returns
+    /// whether the stream was being tracked.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be a valid pointer to a FILE, not yet closed.
+    pub unsafe fn reset(stream: *mut FILE) -> bool {
+        let Some(mut open_files) = lock_tracked() else {
+            return false;
+        };
+        match open_files.stream(stream) {
+            Some(state) => {
+                *state = StreamState::new(state.fd, state.identity, state.auth_file);
+                true
+            }
+            None => false,
+        }
     }
 
     /// Remove a stream from the global state. Tracks that a stream has been
-    /// closed with `fclose`.
-    pub fn remove(stream: *mut FILE) {
-        OPEN_FILES.lock().unwrap().remove(&(stream as usize));
+    /// closed with `fclose` (or reopened with `freopen`). Returns the auth
+    /// file it was, if it was being tracked.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be a valid pointer to a FILE, not yet closed.
+    pub unsafe fn remove(stream: *mut FILE) -> Option<&'static AuthFile> {
+        let mut open_files = lock_tracked()?;
+        let auth_file = open_files.stream(stream)?.auth_file;
+        open_files.remove_stream(stream as usize);
+        Some(auth_file)
+    }
+
+    /// Remove all the streams from the global state. Tracks that all streams
+    /// have been closed with `fcloseall`.
+    pub fn remove_all() {
+        let Some(mut open_files) = lock_tracked() else {
+            return;
+        };
+        let keys: Vec<usize> = open_files.streams.keys().copied().collect();
+        for key in keys {
+            open_files.remove_stream(key);
+        }
+    }
+
+    /// Add a new file descriptor to the global state. Tracks that a new file
+    /// descriptor has been opened with `open` on `auth_file`.
+    pub fn add_descriptor(fd: c_int, auth_file: &'static AuthFile) {
+        let identity = matching::descriptor_identity(fd);
+        let mut open_files = lock();
+        let previous_value = open_files
+            .descriptors
+            .insert(fd, DescriptorState::new(identity, auth_file));
+        if previous_value.is_some() {
+            warning!("File descriptor {fd} was already in the map");
+        }
+        open_files.count_entries();
+    }
+
+    /// Run `f` on the virtual content of a file descriptor, loading it first
+    /// if it hasn't been loaded yet. Returns `None` (without running `f`) if
+    /// the file descriptor is not being tracked.
+    pub fn with_descriptor_content<R>(fd: c_int, f: impl FnOnce(&mut Content) -> R) -> Option<R> {
+        let auth_file = {
+            let mut open_files = lock_tracked()?;
+            let state = open_files.descriptor(fd)?;
+            if let Some(content) = state.content.as_mut() {
+                return Some(f(content));
+            }
+            state.auth_file
+        };
+        // Loaded without holding the lock. See `with_content`.
+        let content = Content::load_descriptor(fd, auth_file);
+        let mut open_files = lock_tracked()?;
+        let state = open_files.descriptor(fd)?;
+        Some(f(state.content.get_or_insert(content)))
+    }
+
+    /// Remove a file descriptor from the global state. Tracks that a file
+    /// descriptor has been closed with `close`. Returns whether the file
+    /// descriptor was being tracked.
+    pub fn remove_descriptor(fd: c_int) -> bool {
+        let Some(mut open_files) = lock_tracked() else {
+            return false;
+        };
+        let removed = open_files.descriptors.remove(&fd).is_some();
+        open_files.count_entries();
+        removed
+    }
+}
+
+/// Lock the global state.
+///
+/// A panic while the lock is held (which would be a bug) poisons it, but the
+/// state is still usable: at worst, a stream is served a partially read line.
+/// So the poisoning is ignored, instead of panicking again (and thus
+/// aborting, as our hooks are called from C).
+fn lock() -> MutexGuard<'static, OpenFiles> {
+    OPEN_FILES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Lock the global state, unless nothing is being tracked (see
+/// `State::is_tracking`).
+fn lock_tracked() -> Option<MutexGuard<'static, OpenFiles>> {
+    State::is_tracking().then(lock)
+}
+
+/// Make the global state safe across `fork`.
+///
+/// Only the thread calling `fork` survives in the child process. If any other
+/// thread was holding the lock at that moment, it would stay locked forever in
+/// the child, which would deadlock as soon as it used any of our hooks. To
+/// prevent that, the forking thread acquires the lock right before forking,
+/// so that no other thread holds it, and releases it right after forking, both
+/// in the parent and in the child.
+fn register_fork_handlers() {
+    extern "C" fn prepare() {
+        let guard = lock();
+        FORK_GUARD.with(|fork_guard| *fork_guard.borrow_mut() = Some(guard));
+    }
+
+    extern "C" fn release() {
+        FORK_GUARD.with(|fork_guard| fork_guard.borrow_mut().take());
+    }
+
+    let result = unsafe { libc::pthread_atfork(Some(prepare), Some(release), Some(release)) };
+    if result != 0 {
+        warning!("Unable to register the fork handlers (error {result})");
+    }
+}
+
+/// Tracked streams (by address) and file descriptors.
+///
+/// Neither addresses nor file descriptors identify a file uniquely over time:
+/// once a stream is released (or a file descriptor closed) without us
+/// noticing (e.g., with `fcloseall`, or with a direct `close` system call),
+/// the same address (or number) can be reused for an unrelated file. So every
+/// entry remembers the identity (device and inode numbers) of its file, and it
+/// is validated on every access. Stale entries are evicted as soon as they are
+/// found, so that unrelated files never inherit their state.
+#[derive(Default)]
+struct OpenFiles {
+    streams: HashMap<usize, StreamState>,
+    descriptors: HashMap<c_int, DescriptorState>,
+}
+
+impl OpenFiles {
+    /// State of `stream`, if it is tracked and the entry is not stale.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must be a valid pointer to a FILE, not yet closed.
+    unsafe fn stream(&mut self, stream: *mut FILE) -> Option<&mut StreamState> {
+        guard::inject_panic("state");
+        let key = stream as usize;
+        let state = self.streams.get(&key)?;
+        let fd = libc::fileno(stream);
+        if fd != state.fd || matching::descriptor_identity(fd) != state.identity {
+            warning!("Stream {stream:p} was reused for a different file");
+            self.remove_stream(key);
+            return None;
+        }
+        self.streams.get_mut(&key)
+    }
+
+    /// State of `fd`, if it is tracked and the entry is not stale.
+    fn descriptor(&mut self, fd: c_int) -> Option<&mut DescriptorState> {
+        let state = self.descriptors.get(&fd)?;
+        if matching::descriptor_identity(fd) != state.identity {
+            warning!("File descriptor {fd} was reused for a different file");
+            self.descriptors.remove(&fd);
+            self.count_entries();
+            return None;
+        }
+        self.descriptors.get_mut(&fd)
+    }
+
+    /// Remove the stream with address `key`, together with its file
+    /// descriptor.
+    fn remove_stream(&mut self, key: usize) {
+        if let Some(state) = self.streams.remove(&key) {
+            if self
+                .descriptors
+                .get(&state.fd)
+                .is_some_and(|descriptor| descriptor.identity == state.identity)
+            {
+                self.descriptors.remove(&state.fd);
+            }
+        }
+        self.count_entries();
+    }
+
+    /// Update `TRACKED_ENTRIES` after adding or removing entries.
+    fn count_entries(&self) {
+        TRACKED_ENTRIES.store(
+            self.streams.len() + self.descriptors.len(),
+            Ordering::Release,
+        );
     }
 }
 
 struct StreamState {
+    fd: c_int,
+    identity: Option<FileIdentity>,
+    auth_file: &'static AuthFile,
+    /// Number of bytes read from the underlying file (before loading the
+    /// content).
+    offset: usize,
+    /// Offset at which the line being read starts.
+    line_start: usize,
+    /// Number of complete lines (i.e., newlines) read.
     lines: usize,
+    content: Option<Content>,
 }
 
 impl StreamState {
-    fn new() -> Self {
-        StreamState { lines: 0 }
+    fn new(fd: c_int, identity: Option<FileIdentity>, auth_file: &'static AuthFile) -> Self {
+        StreamState {
+            fd,
+            identity,
+            auth_file,
+            offset: 0,
+            line_start: 0,
+            lines: 0,
+            content: None,
+        }
+    }
+
+    fn advance(&mut self, bytes: &[u8]) {
+        for (index, _) in bytes.iter().enumerate().filter(|(_, &byte)| byte == b'\n') {
+            self.lines = self.lines.saturating_add(1);
+            self.line_start = self.offset + index + 1;
+        }
+        self.offset += bytes.len();
+    }
+
+    fn is_at_line_start(&self) -> bool {
+        self.offset == self.line_start
     }
+}
+
+struct DescriptorState {
+    identity: Option<FileIdentity>,
+    auth_file: &'static AuthFile,
+    content: Option<Content>,
+}
 
-    fn inc(&mut self) -> usize {
-        self.lines = self.lines.saturating_add(1);
-        self.lines
+impl DescriptorState {
+    fn new(identity: Option<FileIdentity>, auth_file: &'static AuthFile) -> Self {
+        DescriptorState {
+            identity,
+            auth_file,
+            content: None,
+        }
     }
 }
@@ -0,0 +1,42 @@
+// Variadic replacements, which can't be defined in stable Rust.
+//
+// These are not exported directly. The exported symbols are naked Rust
+// functions (see `readers.rs`) that jump here. They just forward the variable
+// arguments as a `va_list` to the corresponding `v*` function, which resolves
+// to our Rust replacement.
+//
+// `stdio.h` is deliberately not included, as it redirects some of these
+// declarations to different symbols depending on the compilation mode (e.g.,
+// `vfscanf` to `__isoc99_vfscanf`).
+
+#include <stdarg.h>
+
+typedef struct _IO_FILE FILE;
+
+extern int vfscanf(FILE *stream, const char *format, va_list ap);
+extern int __isoc99_vfscanf(FILE *stream, const char *format, va_list ap);
+extern int __isoc23_vfscanf(FILE *stream, const char *format, va_list ap);
+
+int openvpn_auth_hook_fscanf(FILE *stream, const char *format, ...) {
+  va_list ap;
+  va_start(ap, format);
+  int result = vfscanf(stream, format, ap);
+  va_end(ap);
+  return result;
+}
+
+int openvpn_auth_hook_isoc99_fscanf(FILE *stream, const char *format, ...) {
+  va_list ap;
+  va_start(ap, format);
+  int result = __isoc99_vfscanf(stream, format, ap);
+  va_end(ap);
+  return result;
+}
+
+int openvpn_auth_hook_isoc23_fscanf(FILE *stream, const char *format, ...) {
+  va_list ap;
+  va_start(ap, format);
+  int result = __isoc23_vfscanf(stream, format, ap);
+  va_end(ap);
+  return result;
+}
@@ -0,0 +1,123 @@
+//! Tests of the parser of OpenVPN options (`src/config.rs`), which is built
+//! here on its own.
+//!
+//! Every `<name>.conf` file in `tests/configs` is parsed as if OpenVPN were
+//! started there with `--config <name>.conf`, and what is found is compared
+//! with `<name>.expected`: one line per file with credentials (the directive
+//! that names it, its path as given and its absolute path, quoted), followed
+//! by one `error: ` line per problem. `$DIR` stands for `tests/configs`.
+
+use std::{fs, path::Path};
+
+#[allow(dead_code)]
+#[path = "../src/config.rs"]
+mod config;
+
+use config::{CredentialKind, Options};
+
+/// Every sample configuration gives the expected files and errors.
+#[test]
+fn test_sample_configs() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/configs");
+    let mut samples: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "conf")
+        })
+        .collect();
+    samples.sort();
+    assert!(!samples.is_empty());
+    for sample in samples {
+        let name = sample.file_name().unwrap().to_str().unwrap();
+        let options = config::parse_command_line(&[b"--config", name.as_bytes()], &dir);
+        let expected = fs::read_to_string(sample.with_extension("expected"))
+            .unwrap()
+            .replace("$DIR", dir.to_str().unwrap());
+        assert_eq!(describe(&options), expected, "in {name}");
+    }
+}
+
+/// Options given on the command line are parsed like the ones in
+/// configuration files, and `--cd` applies to the files named before it too.
+#[test]
+fn test_command_line() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/configs");
+    let options = config::parse_command_line(
+        &[
+            b"--client",
+            b"--auth-user-pass",
+            b"creds.txt",
+            b"--config",
+            b"included/common.conf",
+            b"--cd",
+            b"included",
+            b"--socks-proxy",
+            b"10.0.0.1",
+            b"1080",
+            b"socks.txt",
+        ],
+        &dir,
+    );
+    let expected = "\
+        auth-user-pass \"creds.txt\" \"$DIR/included/creds.txt\"\n\
+        askpass \"key.pass\" \"$DIR/included/key.pass\"\n\
+        http-proxy \"proxy.txt\" \"$DIR/included/proxy.txt\"\n\
+        socks-proxy \"socks.txt\" \"$DIR/included/socks.txt\"\n";
+    assert_eq!(
+        describe(&options),
+        expected.replace("$DIR", dir.to_str().unwrap())
+    );
+}
+
+/// `openvpn <file>` is short for `openvpn --config <file>`, and arguments
+/// before the first option are ignored otherwise.
+#[test]
+fn test_command_line_short_form() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/configs");
+    let options = config::parse_command_line(&[b"inline.conf"], &dir);
+    assert_eq!(
+        describe(&options),
+        format!(
+            "<auth-user-pass> \"inline.conf\" \"{}/inline.conf\"\n",
+            dir.display()
+        )
+    );
+    let options = config::parse_command_line(&[b"inline.conf", b"--verb", b"3"], &dir);
+    assert_eq!(describe(&options), "");
+}
+
+/// Inline blocks are found in the configuration, with the range of their
+/// content.
+#[test]
+fn test_inline_block() {
+    let config = b"client\n<ca>\ncert\n</ca>\n<auth-user-pass>\nuser\npass\n</auth-user-pass>\n";
+    let block = config::inline_block(config, b"auth-user-pass").unwrap();
+    assert_eq!(&config[block], b"user\npass\n");
+    assert!(config::inline_block(config, b"tls-auth").is_none());
+    assert!(config::inline_block(b"<auth-user-pass>\nuser\n", b"auth-user-pass").is_none());
+}
+
+/// What `options` found, in the format of the `.expected` files.
+fn describe(options: &Options) -> String {
+    let mut description = String::new();
+    for file in &options.credential_files {
+        let kind = match file.kind {
+            CredentialKind::AuthUserPass => "auth-user-pass",
+            CredentialKind::InlineAuthUserPass => "<auth-user-pass>",
+            CredentialKind::Askpass => "askpass",
+            CredentialKind::HttpProxy => "http-proxy",
+            CredentialKind::SocksProxy => "socks-proxy",
+        };
+        description += &format!(
+            "{kind} {:?} {:?}\n",
+            String::from_utf8_lossy(&file.path),
+            file.absolute_path.to_string_lossy()
+        );
+    }
+    for error in &options.errors {
+        description += &format!("error: {error}\n");
+    }
+    description
+}
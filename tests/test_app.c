@@ -1,32 +1,650 @@
+#define _GNU_SOURCE
+#include <dlfcn.h>
+#include <fcntl.h>
+#include <pthread.h>
 #include <stdio.h>
 #include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+// Fortified variant of `fgets`, called instead of `fgets` by programs compiled
+// with `-D_FORTIFY_SOURCE`. Declared here so that we can call it explicitly,
+// regardless of the compiler flags used to build this application.
+extern char *__fgets_chk(char *buf, size_t size, int n, FILE *stream);
+
+static void usage(const char *program) {
+  printf("Usage: %s [-o fopen|fopen64|freopen|freopen64|fdopen|open|open64|"
+         "openat] "
+         "[-r fgets|fgets_chk|fgets_unlocked|getline|getdelim|fgetc|getc|"
+         "fgetc_unlocked|getc_unlocked|fread|fread_unlocked|fread_overflow|"
+         "fscanf|fgets_fread|ungetc|read|pread] "
+         "[-s rewind|fseek|fseeko|fseek_cur|fsetpos|lseek|lseek64|lseek_cur|"
+         "lseek_end] [-m mode] "
+         "[-x libc_fclose|close_syscall] [-u <other_filename>] [-t threads] [-c] "
+         "[-p <proxy_filename>] [-w] [-d <directory>] "
+         "<filename> <buffer_size> [<openvpn options>...]\n",
+         program);
+}
+
+// Whether `opener` opens a file descriptor instead of a stream.
+static int is_descriptor_opener(const char *opener) {
+  return strcmp(opener, "open") == 0 || strcmp(opener, "open64") == 0 ||
+         strcmp(opener, "openat") == 0;
+}
+
+// Whether `reader` reads from a file descriptor instead of from a stream.
+static int is_descriptor_reader(const char *reader) {
+  return strcmp(reader, "read") == 0 || strcmp(reader, "pread") == 0;
+}
+
+// Flags for `open` equivalent to the `fopen` mode `mode` (read-only, unless
+// it's an update mode).
+static int open_flags(const char *mode) {
+  return strchr(mode, '+') == NULL ? O_RDONLY : O_RDWR;
+}
+
+// Open `filename` for reading with the function named `opener` (one of the
+// `open` family), with flags equivalent to the `fopen` mode `mode`. Returns -1
+// on error.
+static int open_descriptor(const char *opener, const char *filename,
+                           const char *mode) {
+  if (strcmp(opener, "open") == 0) {
+    return open(filename, open_flags(mode));
+  } else if (strcmp(opener, "open64") == 0) {
+    return open64(filename, open_flags(mode));
+  } else if (strcmp(opener, "openat") == 0) {
+    return openat(AT_FDCWD, filename, open_flags(mode));
+  }
+  return -1;
+}
+
+// Open `filename` for reading with the function named `opener`, in mode
+// `mode`. Returns NULL on error.
+static FILE *open_file(const char *opener, const char *filename,
+                       const char *mode) {
+  if (strcmp(opener, "fopen") == 0) {
+    return fopen(filename, mode);
+  } else if (strcmp(opener, "fopen64") == 0) {
+    return fopen64(filename, mode);
+  } else if (strcmp(opener, "freopen") == 0) {
+    FILE *file = fopen("/dev/null", "r");
+    return file == NULL ? NULL : freopen(filename, mode, file);
+  } else if (strcmp(opener, "freopen64") == 0) {
+    FILE *file = fopen("/dev/null", "r");
+    return file == NULL ? NULL : freopen64(filename, mode, file);
+  } else if (strcmp(opener, "fdopen") == 0) {
+    int fd = open(filename, open_flags(mode));
+    return fd == -1 ? NULL : fdopen(fd, mode);
+  }
+  return NULL;
+}
+
+// Write the contents of `file` to `out`, read with the function named
+// `reader`, using `buffer` (of `buffer_size` bytes) when the function needs
+// one. Returns non-zero if the reader is unknown.
+static int read_file(const char *reader, FILE *file, char *buffer,
+                     int buffer_size, FILE *out) {
+  if (strcmp(reader, "fgets") == 0) {
+    while (fgets(buffer, buffer_size, file) != NULL) {
+      fprintf(out, "%s", buffer);
+    }
+  } else if (strcmp(reader, "fgets_chk") == 0) {
+    while (__fgets_chk(buffer, buffer_size, buffer_size, file) != NULL) {
+      fprintf(out, "%s", buffer);
+    }
+  } else if (strcmp(reader, "fgets_unlocked") == 0) {
+    while (fgets_unlocked(buffer, buffer_size, file) != NULL) {
+      fprintf(out, "%s", buffer);
+    }
+  } else if (strcmp(reader, "getline") == 0) {
+    char *line = NULL;
+    size_t line_size = 0;
+    while (getline(&line, &line_size, file) != -1) {
+      fprintf(out, "%s", line);
+    }
+    free(line);
+  } else if (strcmp(reader, "getdelim") == 0) {
+    char *line = NULL;
+    size_t line_size = 0;
+    while (getdelim(&line, &line_size, '\n', file) != -1) {
+      fprintf(out, "%s", line);
+    }
+    free(line);
+  } else if (strcmp(reader, "fgetc") == 0) {
+    int c;
+    while ((c = fgetc(file)) != EOF) {
+      putc(c, out);
+    }
+  } else if (strcmp(reader, "getc") == 0) {
+    // Relies on `feof` instead of on the `EOF` return value
+    for (int c = getc(file); !feof(file); c = getc(file)) {
+      putc(c, out);
+    }
+  } else if (strcmp(reader, "fgetc_unlocked") == 0) {
+    int c;
+    while ((c = fgetc_unlocked(file)) != EOF) {
+      putc(c, out);
+    }
+  } else if (strcmp(reader, "getc_unlocked") == 0) {
+    int c;
+    while ((c = getc_unlocked(file)) != EOF) {
+      putc(c, out);
+    }
+  } else if (strcmp(reader, "fread") == 0) {
+    size_t n;
+    while ((n = fread(buffer, 1, buffer_size, file)) > 0) {
+      fwrite(buffer, 1, n, out);
+    }
+  } else if (strcmp(reader, "fread_unlocked") == 0) {
+    size_t n;
+    while ((n = fread_unlocked(buffer, 1, buffer_size, file)) > 0) {
+      fwrite(buffer, 1, n, out);
+    }
+  } else if (strcmp(reader, "fread_overflow") == 0) {
+    // First an `fread` whose size in bytes overflows (and wraps around to 0
+    // in glibc), which must read nothing, then the file with `fread`
+    size_t huge = (size_t)1 << (sizeof(size_t) * 4);
+    if (fread(buffer, huge, huge, file) != 0) {
+      return 1;
+    }
+    size_t n;
+    while ((n = fread(buffer, 1, buffer_size, file)) > 0) {
+      fwrite(buffer, 1, n, out);
+    }
+  } else if (strcmp(reader, "fscanf") == 0) {
+    // Prints one whitespace-separated word per line
+    while (fscanf(file, "%s", buffer) == 1) {
+      fprintf(out, "%s\n", buffer);
+    }
+  } else if (strcmp(reader, "ungetc") == 0) {
+    // Reads every character twice, pushing it back in between
+    int c;
+    while ((c = getc(file)) != EOF) {
+      if (ungetc(c, file) != c || getc(file) != c) {
+        return 1;
+      }
+      putc(c, out);
+    }
+  } else if (strcmp(reader, "fgets_fread") == 0) {
+    // First line with `fgets`, then the rest of the file with `fread`
+    if (fgets(buffer, buffer_size, file) != NULL) {
+      fprintf(out, "%s", buffer);
+    }
+    size_t n;
+    while ((n = fread(buffer, 1, buffer_size, file)) > 0) {
+      fwrite(buffer, 1, n, out);
+    }
+  } else {
+    return 1;
+  }
+  return 0;
+}
+
+// Write the contents of the file referred to by `fd` to `out`, read with the
+// function named `reader` (one of the `read` family) into `buffer` (of
+// `buffer_size` bytes). Returns non-zero if the reader is unknown.
+static int read_descriptor(const char *reader, int fd, char *buffer,
+                           int buffer_size, FILE *out) {
+  ssize_t n;
+  if (strcmp(reader, "read") == 0) {
+    while ((n = read(fd, buffer, buffer_size)) > 0) {
+      fwrite(buffer, 1, n, out);
+    }
+  } else if (strcmp(reader, "pread") == 0) {
+    off_t offset = 0;
+    while ((n = pread(fd, buffer, buffer_size, offset)) > 0) {
+      fwrite(buffer, 1, n, out);
+      offset += n;
+    }
+  } else {
+    return 1;
+  }
+  return 0;
+}
+
+// Move `file` back to its beginning (`start`, as returned by `fgetpos` right
+// after opening it) with the function named `seeker`. Returns non-zero if the
+// seeker is unknown or fails.
+static int seek_start(const char *seeker, FILE *file, const fpos_t *start) {
+  if (strcmp(seeker, "rewind") == 0) {
+    rewind(file);
+    return 0;
+  } else if (strcmp(seeker, "fseek") == 0) {
+    return fseek(file, 0, SEEK_SET);
+  } else if (strcmp(seeker, "fseeko") == 0) {
+    return fseeko(file, 0, SEEK_SET);
+  } else if (strcmp(seeker, "fseek_cur") == 0) {
+    // Relies on `ftell` reporting the position within the file
+    return fseek(file, -ftell(file), SEEK_CUR);
+  } else if (strcmp(seeker, "fsetpos") == 0) {
+    return fsetpos(file, start);
+  }
+  return 1;
+}
+
+// Move `fd` back to the beginning of its file with the function named
+// `seeker` (one of the `lseek` family). Returns non-zero if the seeker is
+// unknown or fails.
+static int seek_start_descriptor(const char *seeker, int fd) {
+  off_t offset;
+  if (strcmp(seeker, "lseek") == 0) {
+    offset = lseek(fd, 0, SEEK_SET);
+  } else if (strcmp(seeker, "lseek64") == 0) {
+    offset = lseek64(fd, 0, SEEK_SET);
+  } else if (strcmp(seeker, "lseek_cur") == 0) {
+    // Relies on `lseek` reporting the position within the file
+    offset = lseek(fd, -lseek(fd, 0, SEEK_CUR), SEEK_CUR);
+  } else if (strcmp(seeker, "lseek_end") == 0) {
+    // Relies on `lseek` reporting the size of the file
+    offset = lseek(fd, -lseek(fd, 0, SEEK_END), SEEK_END);
+  } else {
+    return 1;
+  }
+  return offset != 0;
+}
+
+// Open `filename` with the function named `opener`, in mode `mode`, storing
+// the result in `file` (for stream openers) and `fd`. Descriptor readers can
+// also read streams, through their underlying file descriptor (like C++'s
+// `std::ifstream` does). Returns non-zero on error.
+static int open_any(const char *opener, const char *filename, const char *mode,
+                    const char *reader, FILE **file, int *fd) {
+  *file = NULL;
+  if (is_descriptor_opener(opener)) {
+    *fd = open_descriptor(opener, filename, mode);
+  } else {
+    *file = open_file(opener, filename, mode);
+    *fd = *file == NULL ? -1 : fileno(*file);
+  }
+  return *fd == -1 || (*file == NULL && !is_descriptor_reader(reader));
+}
+
+// Read the first line of `file` (or the first byte of `fd`, if there is no
+// stream), and then release it without calling `fclose` nor `close`, with the
+// function named `releaser`. Returns non-zero if the releaser is unknown or
+// fails.
+static int release(const char *releaser, FILE *file, int fd, char *buffer,
+                   int buffer_size) {
+  if (file != NULL ? fgets(buffer, buffer_size, file) == NULL
+                   : read(fd, buffer, 1) != 1) {
+    return 1;
+  }
+  if (strcmp(releaser, "libc_fclose") == 0) {
+    // Calls libc's `fclose` directly, like libraries that bind to it directly
+    // (or libc itself) would do
+    void *libc = dlopen("libc.so.6", RTLD_LAZY | RTLD_NOLOAD);
+    int (*libc_fclose)(FILE *) =
+        libc == NULL ? NULL : (int (*)(FILE *))dlsym(libc, "fclose");
+    return libc_fclose == NULL || file == NULL || libc_fclose(file) != 0;
+  } else if (strcmp(releaser, "close_syscall") == 0) {
+    return syscall(SYS_close, fd) != 0;
+  }
+  return 1;
+}
+
+// Print what `stat` and `access` say about `filename`: whether it's a regular
+// file, its permissions and whether it's readable. Returns non-zero if it
+// can't be stat'ed.
+static int check_file(const char *filename) {
+  struct stat st;
+  if (stat(filename, &st) != 0) {
+    return 1;
+  }
+  printf("%s %o %s\n", S_ISREG(st.st_mode) ? "regular" : "other",
+         st.st_mode & 0777,
+         access(filename, R_OK) == 0 ? "readable" : "unreadable");
+  return 0;
+}
+
+// Swap the files at `filename` and `other_filename`, renaming them through a
+// temporary name. Returns non-zero on error.
+static int swap_files(const char *filename, const char *other_filename) {
+  char temporary[4096];
+  snprintf(temporary, sizeof(temporary), "%s.swap", filename);
+  return rename(filename, temporary) != 0 ||
+         rename(other_filename, filename) != 0 ||
+         rename(temporary, other_filename) != 0;
+}
+
+// Size of the buffers that OpenVPN reads credentials into (`USER_PASS_LEN`, in
+// builds with PKCS#11 support, as distro packages are).
+#define USER_PASS_LEN 4096
+
+// Read the credentials in `filename` the way OpenVPN does for `auth-user-pass`
+// files and proxy authfiles (see `get_user_pass_cr`): the username and then the
+// password with `fgets`, each into its own buffer, without their line endings.
+// Prints them prefixed with `prefix`. Returns non-zero on error.
+static int read_credentials(const char *filename, const char *prefix) {
+  char username[USER_PASS_LEN];
+  char password[USER_PASS_LEN] = "";
+  FILE *fp = fopen(filename, "r");
+  if (fp == NULL) {
+    return 1;
+  }
+  if (fgets(username, sizeof(username), fp) == NULL) {
+    fclose(fp);
+    return 1;
+  }
+  username[strcspn(username, "\r\n")] = '\0';
+  if (fgets(password, sizeof(password), fp) != NULL) {
+    password[strcspn(password, "\r\n")] = '\0';
+  }
+  fclose(fp);
+  printf("%s username: %s\n%s password: %s\n", prefix, username, prefix,
+         password);
+  return 0;
+}
+
+// Number of times each thread reads the file in the stress test.
+#define STRESS_ITERATIONS 200
+
+// In the stress test, every this many iterations the file is read from a
+// forked child process instead of from the thread itself.
+#define STRESS_FORK_INTERVAL 20
+
+// Seconds that a forked child process can take to read the file before it's
+// considered deadlocked.
+#define STRESS_CHILD_TIMEOUT 10
+
+// Parameters of the stress test, shared by all threads.
+struct stress {
+  const char *opener;
+  const char *reader;
+  const char *mode;
+  const char *filename;
+  int buffer_size;
+  // Contents of the file as read before starting the stress test
+  char *expected;
+  size_t expected_size;
+};
+
+// Open, read and close the file, storing its contents in `contents` (of
+// `size` bytes, to be freed by the caller). Returns non-zero on error.
+static int read_to_memory(const struct stress *stress, char **contents,
+                          size_t *size) {
+  FILE *file;
+  int fd;
+  if (open_any(stress->opener, stress->filename, stress->mode, stress->reader,
+               &file, &fd) != 0) {
+    return 1;
+  }
+  char *buffer = (char *)malloc(stress->buffer_size);
+  *contents = NULL;
+  FILE *out = open_memstream(contents, size);
+  int result = out == NULL;
+  if (result == 0) {
+    result = is_descriptor_reader(stress->reader)
+                 ? read_descriptor(stress->reader, fd, buffer,
+                                   stress->buffer_size, out)
+                 : read_file(stress->reader, file, buffer,
+                             stress->buffer_size, out);
+    fclose(out);
+  }
+  free(buffer);
+  if (file != NULL) {
+    fclose(file);
+  } else {
+    close(fd);
+  }
+  return result;
+}
+
+// Read the file once and check that its contents are the expected ones.
+// Returns non-zero otherwise.
+static int stress_read(const struct stress *stress) {
+  char *contents;
+  size_t size;
+  int result = read_to_memory(stress, &contents, &size) != 0 ||
+               size != stress->expected_size ||
+               memcmp(contents, stress->expected, size) != 0;
+  free(contents);
+  return result;
+}
+
+// Like `stress_read`, but in a forked child process.
+static int stress_read_in_child(const struct stress *stress) {
+  pid_t pid = fork();
+  if (pid == -1) {
+    return 1;
+  } else if (pid == 0) {
+    alarm(STRESS_CHILD_TIMEOUT);
+    _exit(stress_read(stress));
+  }
+  int status;
+  return waitpid(pid, &status, 0) != pid || !WIFEXITED(status) ||
+         WEXITSTATUS(status) != 0;
+}
+
+// Body of each thread of the stress test. Returns the number of failed reads.
+static void *stress_thread(void *arg) {
+  const struct stress *stress = (const struct stress *)arg;
+  long failures = 0;
+  for (int i = 0; i < STRESS_ITERATIONS; i++) {
+    failures += i % STRESS_FORK_INTERVAL == 0 ? stress_read_in_child(stress)
+                                              : stress_read(stress);
+  }
+  return (void *)failures;
+}
+
+// Read the file concurrently from `threads` threads (and from processes forked
+// from them), checking that every read gets the same contents as a read done
+// beforehand. Returns the number of failed reads.
+static long stress_test(struct stress *stress, int threads) {
+  if (read_to_memory(stress, &stress->expected, &stress->expected_size) !=
+      0) {
+    return 1;
+  }
+  pthread_t *ids = (pthread_t *)malloc(threads * sizeof(pthread_t));
+  long failures = 0;
+  int started = 0;
+  for (; started < threads; started++) {
+    if (pthread_create(&ids[started], NULL, stress_thread, stress) != 0) {
+      failures++;
+      break;
+    }
+  }
+  for (int i = 0; i < started; i++) {
+    void *thread_failures;
+    pthread_join(ids[i], &thread_failures);
+    failures += (long)thread_failures;
+  }
+  free(ids);
+  free(stress->expected);
+  return failures;
+}
 
 int main(int argc, char *argv[]) {
+  // Read command line options
+  const char *opener = "fopen";
+  const char *reader = "fgets";
+  const char *seeker = NULL;
+  const char *mode = "r";
+  const char *releaser = NULL;
+  const char *other_filename = NULL;
+  int threads = 0;
+  int check = 0;
+  const char *proxy_filename = NULL;
+  int swap = 0;
+  const char *directory = NULL;
+  int opt;
+  // Options end at the file name, so that OpenVPN options can follow the
+  // arguments (they are ignored here, but the hook may discover the auth files
+  // in them)
+  while ((opt = getopt(argc, argv, "+o:r:s:m:x:u:t:cp:wd:")) != -1) {
+    switch (opt) {
+    case 'o':
+      opener = optarg;
+      break;
+    case 'r':
+      reader = optarg;
+      break;
+    case 's':
+      seeker = optarg;
+      break;
+    case 'm':
+      mode = optarg;
+      break;
+    case 'x':
+      releaser = optarg;
+      break;
+    case 'u':
+      other_filename = optarg;
+      break;
+    case 't':
+      threads = atoi(optarg);
+      break;
+    case 'c':
+      check = 1;
+      break;
+    case 'p':
+      proxy_filename = optarg;
+      break;
+    case 'w':
+      swap = 1;
+      break;
+    case 'd':
+      directory = optarg;
+      break;
+    default:
+      usage(argv[0]);
+      return 1;
+    }
+  }
+
   // Check if the correct number of command line arguments are provided
-  if (argc != 3) {
-    printf("Usage: %s <filename> <buffer_size>\n", argv[0]);
+  if (argc - optind < 2) {
+    usage(argv[0]);
     return 1;
   }
 
   // Read command line arguments
-  char *filename = argv[1];
-  int buffer_size = atoi(argv[2]);
+  char *filename = argv[optind];
+  int buffer_size = atoi(argv[optind + 1]);
+
+  // If a directory is given, change to it before opening any file, like
+  // OpenVPN does with `--cd`
+  if (directory != NULL && chdir(directory) != 0) {
+    printf("Error: Directory '%s' not found.\n", directory);
+    return 1;
+  }
+
+  // If requested, swap the file with the other one before opening them, as if
+  // they had been replaced after starting
+  if (swap &&
+      (other_filename == NULL || swap_files(filename, other_filename) != 0)) {
+    printf("Error: Unable to swap '%s' with the other file.\n", filename);
+    return 1;
+  }
+
+  // If requested, check the file before opening it
+  if (check && check_file(filename) != 0) {
+    printf("Error: File '%s' not found.\n", filename);
+    return 1;
+  }
+
+  // If a proxy authfile is given, read the proxy credentials first, like
+  // OpenVPN does when connecting through the proxy
+  if (proxy_filename != NULL && read_credentials(proxy_filename, "proxy") != 0) {
+    printf("Error: File '%s' not found.\n", proxy_filename);
+    return 1;
+  }
 
   // Open the file
-  FILE *file = fopen(filename, "r");
-  if (file == NULL) {
+  FILE *file;
+  int fd;
+  if (open_any(opener, filename, mode, reader, &file, &fd) != 0) {
     printf("Error: File '%s' not found.\n", filename);
     return 1;
   }
 
-  // Read and print the file contents
+  // If a releaser and another file are given, release the file behind the
+  // hook's back and then use the other file instead, which may well get the
+  // same `FILE` address or file descriptor number. If only another file is
+  // given, open it too, to read it after the first one
   char *buffer = (char *)malloc(buffer_size);
-  while (fgets(buffer, buffer_size, file) != NULL) {
-    printf("%s", buffer);
+  FILE *other_file = NULL;
+  int other_fd = -1;
+  if (releaser == NULL && other_filename != NULL &&
+      open_any(opener, other_filename, mode, reader, &other_file, &other_fd) !=
+          0) {
+    printf("Error: File '%s' not found.\n", other_filename);
+    return 1;
+  }
+  if (releaser != NULL && other_filename != NULL) {
+    if (release(releaser, file, fd, buffer, buffer_size) != 0) {
+      usage(argv[0]);
+      return 1;
+    }
+    if (open_any(opener, other_filename, mode, reader, &file, &fd) != 0) {
+      printf("Error: File '%s' not found.\n", other_filename);
+      return 1;
+    }
+  }
+
+  // Read and print the file contents. If a seeker is given, go back to the
+  // beginning of the file and read and print it all again
+  fpos_t start;
+  int result = 0;
+  if (seeker != NULL && !is_descriptor_reader(reader) &&
+      (file == NULL || fgetpos(file, &start) != 0)) {
+    result = 1;
+  }
+  if (result == 0) {
+    result = is_descriptor_reader(reader)
+                 ? read_descriptor(reader, fd, buffer, buffer_size, stdout)
+                 : read_file(reader, file, buffer, buffer_size, stdout);
+  }
+  if (result == 0 && seeker != NULL) {
+    result = is_descriptor_reader(reader)
+                 ? seek_start_descriptor(seeker, fd) ||
+                       read_descriptor(reader, fd, buffer, buffer_size, stdout)
+                 : seek_start(seeker, file, &start) ||
+                       read_file(reader, file, buffer, buffer_size, stdout);
+  }
+  if (result == 0 && releaser == NULL && other_filename != NULL) {
+    result =
+        is_descriptor_reader(reader)
+            ? read_descriptor(reader, other_fd, buffer, buffer_size, stdout)
+            : read_file(reader, other_file, buffer, buffer_size, stdout);
+  }
+  if (result != 0) {
+    usage(argv[0]);
+    return 1;
   }
 
   // Close the file and free the buffer memory
-  fclose(file);
+  if (file != NULL) {
+    fclose(file);
+  } else {
+    close(fd);
+  }
+  if (other_file != NULL) {
+    fclose(other_file);
+  } else if (other_fd != -1) {
+    close(other_fd);
+  }
+  free(buffer);
+
+  // If a proxy authfile is given, read the proxy credentials again, like
+  // OpenVPN does when reconnecting through the proxy
+  if (proxy_filename != NULL && read_credentials(proxy_filename, "proxy") != 0) {
+    printf("Error: File '%s' not found.\n", proxy_filename);
+    return 1;
+  }
+
+  // If a number of threads is given, read the file concurrently from all of
+  // them, and from forked children too
+  if (threads > 0) {
+    struct stress stress = {opener, reader, mode, filename, buffer_size, NULL, 0};
+    long failures = stress_test(&stress, threads);
+    if (failures != 0) {
+      fprintf(stderr, "Error: %ld concurrent reads failed\n", failures);
+      return 1;
+    }
+  }
 
   return 0;
 }
@@ -0,0 +1,29 @@
+// Reads a file with `std::ifstream`, which (in libstdc++) opens it with
+// `fopen` but then reads it with `read` on the underlying file descriptor.
+
+#include <fstream>
+#include <iostream>
+#include <string>
+
+int main(int argc, char *argv[]) {
+  // Check if the correct number of command line arguments are provided
+  if (argc != 2) {
+    std::cout << "Usage: " << argv[0] << " <filename>" << std::endl;
+    return 1;
+  }
+
+  // Open the file
+  std::ifstream file(argv[1]);
+  if (!file) {
+    std::cout << "Error: File '" << argv[1] << "' not found." << std::endl;
+    return 1;
+  }
+
+  // Read and print the file contents, line by line
+  std::string line;
+  while (std::getline(file, line)) {
+    std::cout << line << '\n';
+  }
+
+  return 0;
+}